@@ -0,0 +1,330 @@
+use std::cell::RefCell;
+use std::cmp;
+use std::collections::VecDeque;
+use std::io::{stdout, Write};
+use std::rc::Rc;
+use std::thread;
+use std::time::Duration;
+use crossterm::{
+    cursor,
+    event::{self, Event, KeyCode, KeyEvent, KeyModifiers},
+    style::{Print, ResetColor, SetColors},
+    terminal, QueueableCommand,
+};
+use signal_hook::consts::SIGTSTP;
+use crate::Screen;
+
+// everything the editor needs from the terminal it runs in
+pub trait Backend {
+    fn size(&self) -> Result<(u16, u16), std::io::Error>;
+    fn enable_raw_mode(&mut self) -> Result<(), std::io::Error>;
+    fn disable_raw_mode(&mut self) -> Result<(), std::io::Error>;
+    fn enter_alternate_screen(&mut self) -> Result<(), std::io::Error>;
+    fn leave_alternate_screen(&mut self) -> Result<(), std::io::Error>;
+    fn read(&mut self) -> Result<Event, std::io::Error>;
+    fn poll(&mut self, timeout: Duration) -> Result<bool, std::io::Error>;
+    // queues the cells of `screen` that changed since the last frame
+    fn draw(&mut self, screen: &Screen) -> Result<(), std::io::Error>;
+    fn clear_screen(&mut self) -> Result<(), std::io::Error>;
+    fn reset_colors(&mut self) -> Result<(), std::io::Error>;
+    fn set_cursor_position(&mut self, x: u16, y: u16) -> Result<(), std::io::Error>;
+    fn set_cursor_visible(&mut self, visible: bool) -> Result<(), std::io::Error>;
+    fn set_cursor_blinking(&mut self, blinking: bool) -> Result<(), std::io::Error>;
+    fn flush(&mut self) -> Result<(), std::io::Error>;
+    // stops the process until the shell continues it
+    fn suspend(&mut self) -> Result<(), std::io::Error>;
+}
+
+pub struct CrosstermBackend;
+
+impl Backend for CrosstermBackend {
+    fn size(&self) -> Result<(u16, u16), std::io::Error> {
+        terminal::size()
+    }
+
+    fn enable_raw_mode(&mut self) -> Result<(), std::io::Error> {
+        terminal::enable_raw_mode()
+    }
+
+    fn disable_raw_mode(&mut self) -> Result<(), std::io::Error> {
+        terminal::disable_raw_mode()
+    }
+
+    fn enter_alternate_screen(&mut self) -> Result<(), std::io::Error> {
+        stdout().queue(terminal::EnterAlternateScreen)?;
+        Ok(())
+    }
+
+    fn leave_alternate_screen(&mut self) -> Result<(), std::io::Error> {
+        stdout().queue(terminal::LeaveAlternateScreen)?;
+        Ok(())
+    }
+
+    fn read(&mut self) -> Result<Event, std::io::Error> {
+        event::read()
+    }
+
+    fn poll(&mut self, timeout: Duration) -> Result<bool, std::io::Error> {
+        event::poll(timeout)
+    }
+
+    fn draw(&mut self, screen: &Screen) -> Result<(), std::io::Error> {
+        let mut out = stdout();
+        for run in screen.changes() {
+            out.queue(cursor::MoveTo(run.x as u16, run.y as u16))?;
+            let mut colors = None;
+            for cell in run.cells {
+                if colors != Some(cell.colors) {
+                    out.queue(SetColors(cell.colors))?;
+                    colors = Some(cell.colors);
+                }
+                out.queue(Print(&cell.symbol))?;
+            }
+        }
+        out.queue(ResetColor)?;
+        Ok(())
+    }
+
+    fn clear_screen(&mut self) -> Result<(), std::io::Error> {
+        stdout().queue(terminal::Clear(terminal::ClearType::All))?;
+        Ok(())
+    }
+
+    fn reset_colors(&mut self) -> Result<(), std::io::Error> {
+        stdout().queue(ResetColor)?;
+        Ok(())
+    }
+
+    fn set_cursor_position(&mut self, x: u16, y: u16) -> Result<(), std::io::Error> {
+        stdout().queue(cursor::MoveTo(x, y))?;
+        Ok(())
+    }
+
+    fn set_cursor_visible(&mut self, visible: bool) -> Result<(), std::io::Error> {
+        if visible {
+            stdout().queue(cursor::Show)?;
+        }
+        else {
+            stdout().queue(cursor::Hide)?;
+        }
+        Ok(())
+    }
+
+    fn set_cursor_blinking(&mut self, blinking: bool) -> Result<(), std::io::Error> {
+        if blinking {
+            stdout().queue(cursor::EnableBlinking)?;
+        }
+        else {
+            stdout().queue(cursor::DisableBlinking)?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), std::io::Error> {
+        stdout().flush()
+    }
+
+    fn suspend(&mut self) -> Result<(), std::io::Error> {
+        signal_hook::low_level::raise(SIGTSTP)
+    }
+}
+
+// a scripted event, or a stretch of time in which the user does nothing
+enum Scripted {
+    Event(Event),
+    Pause(Duration),
+}
+
+#[derive(Default)]
+struct HeadlessState {
+    events: VecDeque<Scripted>,
+    grid: Screen,
+    frames: Vec<Vec<String>>,
+    cursor: (u16, u16),
+    cursor_visible: bool,
+    raw_mode: bool,
+    alternate_screen: bool,
+    suspensions: usize,
+}
+
+// an in-memory terminal for tests: key events are scripted up front and every
+// flushed frame is captured. Clones share the same state, so a test can keep
+// one handle while the editor owns another
+#[derive(Clone)]
+pub struct HeadlessBackend {
+    state: Rc<RefCell<HeadlessState>>,
+}
+
+impl HeadlessBackend {
+    pub fn new(width: u16, height: u16) -> Self {
+        let state = HeadlessState {
+            grid: Screen::new(width as usize, height as usize),
+            ..HeadlessState::default()
+        };
+        Self {
+            state: Rc::new(RefCell::new(state)),
+        }
+    }
+
+    pub fn push_event(&self, event: Event) {
+        self.state.borrow_mut().events.push_back(Scripted::Event(event));
+    }
+
+    // lets `duration` pass before the next event, so timers get to fire
+    pub fn push_pause(&self, duration: Duration) {
+        self.state.borrow_mut().events.push_back(Scripted::Pause(duration));
+    }
+
+    pub fn push_key(&self, code: KeyCode, modifiers: KeyModifiers) {
+        self.push_event(Event::Key(KeyEvent::new(code, modifiers)));
+    }
+
+    pub fn push_str(&self, text: &str) {
+        for c in text.chars() {
+            if c == '\n' {
+                self.push_key(KeyCode::Enter, KeyModifiers::NONE);
+            }
+            else {
+                self.push_key(KeyCode::Char(c), KeyModifiers::NONE);
+            }
+        }
+    }
+
+    // every frame flushed so far, each as one string per terminal line
+    pub fn frames(&self) -> Vec<Vec<String>> {
+        self.state.borrow().frames.clone()
+    }
+
+    pub fn cursor(&self) -> (u16, u16) {
+        self.state.borrow().cursor
+    }
+
+    pub fn is_cursor_visible(&self) -> bool {
+        self.state.borrow().cursor_visible
+    }
+
+    pub fn is_raw_mode(&self) -> bool {
+        self.state.borrow().raw_mode
+    }
+
+    pub fn is_alternate_screen(&self) -> bool {
+        self.state.borrow().alternate_screen
+    }
+
+    pub fn suspensions(&self) -> usize {
+        self.state.borrow().suspensions
+    }
+}
+
+impl Backend for HeadlessBackend {
+    fn size(&self) -> Result<(u16, u16), std::io::Error> {
+        let state = self.state.borrow();
+        Ok((state.grid.width() as u16, state.grid.height() as u16))
+    }
+
+    fn enable_raw_mode(&mut self) -> Result<(), std::io::Error> {
+        self.state.borrow_mut().raw_mode = true;
+        Ok(())
+    }
+
+    fn disable_raw_mode(&mut self) -> Result<(), std::io::Error> {
+        self.state.borrow_mut().raw_mode = false;
+        Ok(())
+    }
+
+    fn enter_alternate_screen(&mut self) -> Result<(), std::io::Error> {
+        self.state.borrow_mut().alternate_screen = true;
+        Ok(())
+    }
+
+    fn leave_alternate_screen(&mut self) -> Result<(), std::io::Error> {
+        self.state.borrow_mut().alternate_screen = false;
+        Ok(())
+    }
+
+    // sits out any pauses ahead of the next event
+    fn read(&mut self) -> Result<Event, std::io::Error> {
+        loop {
+            let next = self.state.borrow_mut().events.pop_front();
+            match next {
+                Some(Scripted::Event(event)) => return Ok(event),
+                Some(Scripted::Pause(duration)) => thread::sleep(duration),
+                None => return Err(end_of_script()),
+            }
+        }
+    }
+
+    // an event is ready unless a pause comes first, which is waited out for
+    // up to `timeout`. Once the script runs out waiting fails like `read`,
+    // rather than leaving the editor waiting forever
+    fn poll(&mut self, timeout: Duration) -> Result<bool, std::io::Error> {
+        let mut state = self.state.borrow_mut();
+        let left = match state.events.front_mut() {
+            Some(Scripted::Event(_)) => return Ok(true),
+            Some(Scripted::Pause(left)) => left,
+            None if timeout.is_zero() => return Ok(false),
+            None => return Err(end_of_script()),
+        };
+        let waited = cmp::min(*left, timeout);
+        *left -= waited;
+        let over = left.is_zero();
+        if over {
+            state.events.pop_front();
+        }
+        drop(state);
+        thread::sleep(waited);
+        Ok(over && matches!(self.state.borrow().events.front(), Some(Scripted::Event(_))))
+    }
+
+    fn draw(&mut self, screen: &Screen) -> Result<(), std::io::Error> {
+        let mut state = self.state.borrow_mut();
+        for run in screen.changes() {
+            let mut x = run.x;
+            for cell in run.cells {
+                if !cell.symbol.is_empty() {
+                    x = state.grid.put_str(x, run.y, &cell.symbol, cell.colors);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn clear_screen(&mut self) -> Result<(), std::io::Error> {
+        self.state.borrow_mut().grid.clear();
+        Ok(())
+    }
+
+    fn reset_colors(&mut self) -> Result<(), std::io::Error> {
+        Ok(())
+    }
+
+    fn set_cursor_position(&mut self, x: u16, y: u16) -> Result<(), std::io::Error> {
+        self.state.borrow_mut().cursor = (x, y);
+        Ok(())
+    }
+
+    fn set_cursor_visible(&mut self, visible: bool) -> Result<(), std::io::Error> {
+        self.state.borrow_mut().cursor_visible = visible;
+        Ok(())
+    }
+
+    fn set_cursor_blinking(&mut self, _blinking: bool) -> Result<(), std::io::Error> {
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), std::io::Error> {
+        let mut state = self.state.borrow_mut();
+        let frame = (0..state.grid.height()).map(|y| state.grid.line(y)).collect();
+        state.frames.push(frame);
+        Ok(())
+    }
+
+    fn suspend(&mut self) -> Result<(), std::io::Error> {
+        self.state.borrow_mut().suspensions += 1;
+        Ok(())
+    }
+}
+
+fn end_of_script() -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "no more scripted events")
+}
@@ -0,0 +1,49 @@
+use std::fs;
+use std::path::Path;
+
+// an entry of a directory listing
+pub struct Entry {
+    pub name: String,
+    pub is_dir: bool, //symbolic links count as what they point to
+}
+
+// the entries of `dir`, directories first and each group sorted by name
+// without regard to case
+pub fn read_dir(dir: &Path) -> Result<Vec<Entry>, std::io::Error> {
+    let mut entries: Vec<Entry> = fs::read_dir(dir)?
+        .filter_map(Result::ok)
+        .map(|entry| Entry {
+            name: entry.file_name().to_string_lossy().into_owned(),
+            is_dir: entry.path().is_dir(),
+        })
+        .collect();
+    entries.sort_by(|a, b| {
+        (!a.is_dir, a.name.to_lowercase(), &a.name).cmp(&(!b.is_dir, b.name.to_lowercase(), &b.name))
+    });
+    Ok(entries)
+}
+
+// creates the file `name` in `dir`, or the directory if `name` ends with a
+// slash; an existing file is an error rather than being truncated
+pub fn create(dir: &Path, name: &str) -> Result<(), std::io::Error> {
+    let path = dir.join(name);
+    if name.ends_with('/') {
+        fs::create_dir_all(path)
+    }
+    else {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::OpenOptions::new().write(true).create_new(true).open(path).map(drop)
+    }
+}
+
+// directories are only removed when empty
+pub fn delete(path: &Path) -> Result<(), std::io::Error> {
+    if fs::symlink_metadata(path)?.is_dir() {
+        fs::remove_dir(path)
+    }
+    else {
+        fs::remove_file(path)
+    }
+}
@@ -0,0 +1,153 @@
+use encoding_rs::Encoding;
+
+pub const USAGE: &str = "\
+Usage: hecto [OPTIONS] [FILE]...
+
+Opens each FILE in its own buffer; `-` reads the text from standard input.
+
+Options:
+  -r, --readonly          open the files read-only
+  -l, --line N, +N        put the cursor on line N of the first file
+  -e, --encoding NAME     read and write the files in encoding NAME (default utf-8)
+  -c, --config PATH       read the configuration from PATH
+      --no-config         ignore the configuration file
+  -V, --version           print the version and exit
+  -h, --help              print this help and exit
+";
+
+// what to do, as asked for on the command line
+pub enum Command {
+    Run(Args),
+    Help,
+    Version,
+}
+
+#[derive(Default)]
+pub struct Args {
+    pub files: Vec<String>,
+    pub read_only: bool,
+    pub line: Option<usize>,
+    pub encoding: Option<&'static Encoding>,
+    pub config: Option<String>,
+    pub no_config: bool,
+}
+
+impl Command {
+    pub fn parse<I>(args: I) -> Result<Self, String>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let mut parsed = Args::default();
+        let mut args = args.into_iter();
+        let mut options_done = false;
+        while let Some(arg) = args.next() {
+            if options_done || arg == "-" || !arg.starts_with(['-', '+']) {
+                parsed.files.push(arg);
+                continue;
+            }
+            // `--name=value` is the same as `--name value`
+            let (name, inline) = match arg.split_once('=') {
+                Some((name, value)) if name.starts_with("--") => (name.to_string(), Some(value.to_string())),
+                _ => (arg.clone(), None),
+            };
+            let mut value = |name: &str| {
+                inline
+                    .clone()
+                    .or_else(|| args.next())
+                    .ok_or_else(|| format!("option '{}' needs a value", name))
+            };
+            match name.as_str() {
+                "--" => options_done = true,
+                "-h" | "--help" => return Ok(Self::Help),
+                "-V" | "--version" => return Ok(Self::Version),
+                "-r" | "--readonly" => parsed.read_only = true,
+                "--no-config" => parsed.no_config = true,
+                "-c" | "--config" => parsed.config = Some(value(&name)?),
+                "-l" | "--line" => parsed.line = Some(parse_line(&value(&name)?)?),
+                "-e" | "--encoding" => {
+                    let label = value(&name)?;
+                    let encoding = Encoding::for_label(label.as_bytes())
+                        .ok_or_else(|| format!("unknown encoding '{}'", label))?;
+                    parsed.encoding = Some(encoding);
+                },
+                _ => {
+                    if let Some(line) = name.strip_prefix('+') {
+                        parsed.line = Some(parse_line(line)?);
+                    }
+                    else {
+                        return Err(format!("unknown option '{}'", name));
+                    }
+                },
+            }
+        }
+        if parsed.files.iter().filter(|file| *file == "-").count() > 1 {
+            return Err(String::from("standard input can only be read once"));
+        }
+        Ok(Self::Run(parsed))
+    }
+}
+
+fn parse_line(value: &str) -> Result<usize, String> {
+    match value.parse::<usize>() {
+        Ok(line) if line > 0 => Ok(line),
+        _ => Err(format!("invalid line number '{}'", value)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Args, String> {
+        match Command::parse(args.iter().map(|arg| arg.to_string()))? {
+            Command::Run(args) => Ok(args),
+            Command::Help => Err(String::from("help")),
+            Command::Version => Err(String::from("version")),
+        }
+    }
+
+    #[test]
+    fn files_and_flags() {
+        let args = parse(&["a.rs", "-r", "b.rs", "--line", "12", "--no-config"]).unwrap();
+        assert_eq!(args.files, vec!["a.rs", "b.rs"]);
+        assert!(args.read_only);
+        assert!(args.no_config);
+        assert_eq!(args.line, Some(12));
+    }
+
+    #[test]
+    fn plus_line_and_inline_values() {
+        let args = parse(&["+7", "--config=/tmp/hecto.conf", "-"]).unwrap();
+        assert_eq!(args.line, Some(7));
+        assert_eq!(args.config.as_deref(), Some("/tmp/hecto.conf"));
+        assert_eq!(args.files, vec!["-"]);
+    }
+
+    #[test]
+    fn encodings_are_looked_up_by_label() {
+        let args = parse(&["--encoding", "latin1", "a.txt"]).unwrap();
+        assert_eq!(args.encoding.map(Encoding::name), Some("windows-1252"));
+        assert!(parse(&["--encoding", "klingon"]).is_err());
+    }
+
+    #[test]
+    fn help_and_version_win() {
+        assert_eq!(parse(&["a.rs", "--help"]).err().as_deref(), Some("help"));
+        assert_eq!(parse(&["-V"]).err().as_deref(), Some("version"));
+    }
+
+    #[test]
+    fn errors() {
+        assert!(parse(&["--bogus"]).is_err());
+        assert!(parse(&["--line"]).is_err());
+        assert!(parse(&["+0"]).is_err());
+        assert!(parse(&["-", "-"]).is_err());
+    }
+
+    #[test]
+    fn double_dash_ends_options() {
+        let args = parse(&["--", "-r", "+3"]).unwrap();
+        assert_eq!(args.files, vec!["-r", "+3"]);
+        assert!(!args.read_only);
+    }
+}
@@ -0,0 +1,147 @@
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+const DEFAULT_TAB_WIDTH: usize = 4;
+const DEFAULT_LARGE_FILE_THRESHOLD: u64 = 64 * 1024 * 1024;
+const DEFAULT_AUTOSAVE_INTERVAL: u64 = 4;
+
+pub struct Config {
+    pub tab_width: usize,
+    pub large_file_threshold: u64, //files at least this big open read-only from a memory map
+    pub autosave_interval: u64, //seconds between swap file writes, 0 turns them off
+    pub alternate_screen: bool, //draw on the alternate screen instead of over the shell
+    pub sudo_command: String, //runs `tee` to save files the user cannot write
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            tab_width: DEFAULT_TAB_WIDTH,
+            large_file_threshold: DEFAULT_LARGE_FILE_THRESHOLD,
+            autosave_interval: DEFAULT_AUTOSAVE_INTERVAL,
+            alternate_screen: true,
+            sudo_command: String::from("sudo"),
+        }
+    }
+}
+
+impl Config {
+    // reads `key = value` lines from the config file, falling back to defaults
+    pub fn load() -> Self {
+        let mut config = Self::default();
+        if let Some(dir) = Self::dir() {
+            if let Ok(contents) = fs::read_to_string(dir.join("config")) {
+                config.parse(&contents);
+            }
+        }
+        config
+    }
+
+    // reads the config file at `path`, which has to exist
+    pub fn load_from(path: &str) -> Result<Self, std::io::Error> {
+        let mut config = Self::default();
+        config.parse(&fs::read_to_string(path)?);
+        Ok(config)
+    }
+
+    pub fn dir() -> Option<PathBuf> {
+        if let Some(dir) = env::var_os("XDG_CONFIG_HOME") {
+            return Some(PathBuf::from(dir).join("hecto"));
+        }
+        env::var_os("HOME").map(|home| PathBuf::from(home).join(".config").join("hecto"))
+    }
+
+    fn parse(&mut self, contents: &str) {
+        for line in contents.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if let Some((key, value)) = line.split_once('=') {
+                self.set(key.trim(), value.trim());
+            }
+        }
+    }
+
+    fn set(&mut self, key: &str, value: &str) {
+        match key {
+            "tab_width" => {
+                if let Ok(width) = value.parse::<usize>() {
+                    if width > 0 {
+                        self.tab_width = width;
+                    }
+                }
+            },
+            "large_file_threshold" => {
+                if let Some(size) = parse_size(value) {
+                    self.large_file_threshold = size;
+                }
+            },
+            "autosave_interval" => {
+                if let Ok(seconds) = value.parse::<u64>() {
+                    self.autosave_interval = seconds;
+                }
+            },
+            "alternate_screen" => {
+                if let Ok(enabled) = value.parse::<bool>() {
+                    self.alternate_screen = enabled;
+                }
+            },
+            "sudo_command" => {
+                if !value.is_empty() {
+                    self.sudo_command = value.to_string();
+                }
+            },
+            _ => (),
+        }
+    }
+}
+
+// a byte count with an optional K, M or G suffix
+fn parse_size(value: &str) -> Option<u64> {
+    let (number, multiplier) = match value.to_ascii_uppercase().chars().last()? {
+        'K' => (&value[..value.len() - 1], 1024),
+        'M' => (&value[..value.len() - 1], 1024 * 1024),
+        'G' => (&value[..value.len() - 1], 1024 * 1024 * 1024),
+        _ => (value, 1),
+    };
+    number.trim().parse::<u64>().ok()?.checked_mul(multiplier)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn settings_are_read_from_key_value_lines() {
+        let mut config = Config::default();
+        config.parse("# comment\n tab_width = 2\nalternate_screen=false\nsudo_command = doas\nunknown = 1\n");
+        assert_eq!(config.tab_width, 2);
+        assert!(!config.alternate_screen);
+        assert_eq!(config.sudo_command, "doas");
+        assert_eq!(config.autosave_interval, DEFAULT_AUTOSAVE_INTERVAL);
+    }
+
+    #[test]
+    fn invalid_values_keep_the_defaults() {
+        let mut config = Config::default();
+        config.parse("tab_width = 0\nautosave_interval = soon\nsudo_command =\nalternate_screen\n");
+        assert_eq!(config.tab_width, DEFAULT_TAB_WIDTH);
+        assert_eq!(config.autosave_interval, DEFAULT_AUTOSAVE_INTERVAL);
+        assert_eq!(config.sudo_command, "sudo");
+        assert!(config.alternate_screen);
+    }
+
+    #[test]
+    fn sizes_take_an_optional_suffix() {
+        assert_eq!(parse_size("512"), Some(512));
+        assert_eq!(parse_size("4k"), Some(4 * 1024));
+        assert_eq!(parse_size("10 M"), Some(10 * 1024 * 1024));
+        assert_eq!(parse_size("2G"), Some(2 * 1024 * 1024 * 1024));
+        assert_eq!(parse_size(""), None);
+        assert_eq!(parse_size("M"), None);
+        assert_eq!(parse_size("1.5M"), None);
+        assert_eq!(parse_size("99999999999G"), None);
+    }
+}
@@ -0,0 +1,62 @@
+use std::panic;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, ThreadId};
+use signal_hook::consts::{SIGHUP, SIGTERM};
+
+// the panic message is kept here instead of being printed straight away: while
+// the terminal is in raw mode it would be garbled and then cleared, so the
+// editor prints it itself once the terminal has been restored
+static PANIC_MESSAGE: Mutex<Option<String>> = Mutex::new(None);
+// the thread running the editor while it has the terminal in raw mode
+static TERMINAL_OWNER: Mutex<Option<ThreadId>> = Mutex::new(None);
+
+// panics on any other thread, or once the terminal is restored, go to the
+// hook that was installed before
+pub fn install_panic_hook() {
+    let previous = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        let owner = TERMINAL_OWNER.lock().ok().and_then(|owner| *owner);
+        if owner == Some(thread::current().id()) {
+            if let Ok(mut message) = PANIC_MESSAGE.lock() {
+                *message = Some(info.to_string());
+                return;
+            }
+        }
+        previous(info);
+    }));
+}
+
+// marks the current thread as owning the terminal, or no thread
+pub fn own_terminal(owned: bool) {
+    if let Ok(mut owner) = TERMINAL_OWNER.lock() {
+        *owner = if owned { Some(thread::current().id()) } else { None };
+    }
+}
+
+pub fn take_panic_message() -> Option<String> {
+    PANIC_MESSAGE.lock().ok().and_then(|mut message| message.take())
+}
+
+// SIGTERM and SIGHUP are only recorded when they arrive; the editor checks for
+// them between key presses and shuts down cleanly
+pub struct Signals {
+    received: Arc<AtomicUsize>,
+}
+
+impl Signals {
+    pub fn register() -> Self {
+        let received = Arc::new(AtomicUsize::new(0));
+        for signal in [SIGTERM, SIGHUP] {
+            signal_hook::flag::register_usize(signal, Arc::clone(&received), signal as usize).ok();
+        }
+        Self { received }
+    }
+
+    pub fn received(&self) -> Option<i32> {
+        match self.received.load(Ordering::Acquire) {
+            0 => None,
+            signal => i32::try_from(signal).ok(),
+        }
+    }
+}
@@ -0,0 +1,132 @@
+use std::collections::hash_map::DefaultHasher;
+use std::fs;
+use std::hash::Hasher;
+use std::time::SystemTime;
+
+// what the file looked like when it was opened or last saved. A different
+// modification time or size only counts as a change once the contents are
+// found to differ too, so a plain `touch` goes unnoticed
+pub struct DiskState {
+    modified: Option<SystemTime>,
+    len: u64,
+    hash: Option<u64>, //left out for large files, which are never hashed
+    changed: Option<(Option<SystemTime>, u64)>, //newer version already found to differ
+}
+
+impl DiskState {
+    pub fn new<'a, I>(file_name: &str, chunks: I) -> Option<Self>
+    where
+        I: IntoIterator<Item = &'a [u8]>,
+    {
+        let mut state = Self::unhashed(file_name)?;
+        state.hash = Some(hash(chunks));
+        Some(state)
+    }
+
+    pub fn unhashed(file_name: &str) -> Option<Self> {
+        let (modified, len) = stat(file_name)?;
+        Some(Self {
+            modified,
+            len,
+            hash: None,
+            changed: None,
+        })
+    }
+
+    // whether the file has been changed by someone else since; a file that
+    // is gone does not count, as saving simply writes it again
+    pub fn is_changed(&mut self, file_name: &str) -> bool {
+        let current = match stat(file_name) {
+            Some(current) => current,
+            None => return false,
+        };
+        if current == (self.modified, self.len) {
+            return false;
+        }
+        if self.changed == Some(current) {
+            return true;
+        }
+        if let Some(hash_at_open) = self.hash {
+            let contents = fs::read(file_name).unwrap_or_default();
+            if hash([&contents[..]]) == hash_at_open {
+                self.modified = current.0;
+                self.len = current.1;
+                return false;
+            }
+        }
+        self.changed = Some(current);
+        true
+    }
+}
+
+fn stat(file_name: &str) -> Option<(Option<SystemTime>, u64)> {
+    let metadata = fs::metadata(file_name).ok()?;
+    Some((metadata.modified().ok(), metadata.len()))
+}
+
+fn hash<'a, I>(chunks: I) -> u64
+where
+    I: IntoIterator<Item = &'a [u8]>,
+{
+    let mut hasher = DefaultHasher::new();
+    for chunk in chunks {
+        hasher.write(chunk);
+    }
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    fn file(name: &str, contents: &str) -> (PathBuf, String, DiskState) {
+        let path = std::env::temp_dir().join(format!("hecto-disk-{}-{}", std::process::id(), name));
+        fs::write(&path, contents).unwrap();
+        let file_name = path.to_str().unwrap().to_string();
+        let state = DiskState::new(&file_name, [contents.as_bytes()]).unwrap();
+        (path, file_name, state)
+    }
+
+    fn set_modified(path: &PathBuf, modified: SystemTime) {
+        fs::File::options().write(true).open(path).unwrap().set_modified(modified).unwrap();
+    }
+
+    #[test]
+    fn untouched_and_touched_files_are_unchanged() {
+        let (path, file_name, mut state) = file("same", "text\n");
+        assert!(!state.is_changed(&file_name));
+        set_modified(&path, SystemTime::now() + Duration::from_secs(10));
+        assert!(!state.is_changed(&file_name));
+        fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn new_contents_are_a_change() {
+        let (path, file_name, mut state) = file("changed", "text\n");
+        fs::write(&path, "other text\n").unwrap();
+        set_modified(&path, SystemTime::now() + Duration::from_secs(10));
+        assert!(state.is_changed(&file_name));
+        assert!(state.is_changed(&file_name));
+        fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn new_contents_with_the_old_modification_time_are_a_change() {
+        let (path, file_name, mut state) = file("mtime", "text\n");
+        let modified = fs::metadata(&path).unwrap().modified().unwrap();
+        fs::write(&path, "longer text\n").unwrap();
+        set_modified(&path, modified);
+        assert!(state.is_changed(&file_name));
+        fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn deleted_files_are_not_a_change() {
+        let (path, file_name, mut state) = file("deleted", "text\n");
+        fs::remove_file(&path).unwrap();
+        assert!(!state.is_changed(&file_name));
+        assert!(DiskState::unhashed(&file_name).is_none());
+    }
+}
@@ -1,123 +1,1088 @@
 use crate::Row;
 use crate::Position;
+use crate::FileType;
+use crate::history::{Change, History};
+use crate::large_file::LargeFile;
+use crate::disk::DiskState;
+use crate::search::Search;
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::cmp;
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
-use std::io::Write;
+use std::io::{BufWriter, Read, Write};
+use std::rc::Rc;
+use std::time::Instant;
+use encoding_rs::{Encoding, UTF_8};
+use ropey::Rope;
+use unicode_segmentation::UnicodeSegmentation;
 
+const BRACKETS: [(&str, &str); 3] = [("(", ")"), ("[", "]"), ("{", "}")];
+const MAX_BRACKET_SCAN_ROWS: usize = 10_000;
+const ROW_CACHE_SIZE: usize = 1024;
+
+// row views by row index; once full, the least recently used one goes
+#[derive(Default)]
+struct RowCache {
+    rows: HashMap<usize, (Rc<Row>, u64)>, //the row and when it was last used
+    used: BTreeMap<u64, usize>, //row indexes by when they were last used
+    clock: u64,
+}
+
+impl RowCache {
+    fn get(&mut self, idx: usize) -> Option<Rc<Row>> {
+        self.clock += 1;
+        let (row, used) = self.rows.get_mut(&idx)?;
+        self.used.remove(used);
+        *used = self.clock;
+        self.used.insert(self.clock, idx);
+        Some(Rc::clone(row))
+    }
+
+    fn insert(&mut self, idx: usize, row: Rc<Row>) {
+        if self.rows.len() >= ROW_CACHE_SIZE {
+            if let Some((_, oldest)) = self.used.pop_first() {
+                self.rows.remove(&oldest);
+            }
+        }
+        self.clock += 1;
+        if let Some((_, used)) = self.rows.insert(idx, (row, self.clock)) {
+            self.used.remove(&used);
+        }
+        self.used.insert(self.clock, idx);
+    }
+
+    // the cached row to patch in place; a copy if it is still handed out
+    fn get_mut(&mut self, idx: usize) -> Option<&mut Row> {
+        self.rows.get_mut(&idx).map(|(row, _)| Rc::make_mut(row))
+    }
+
+    fn clear(&mut self) {
+        self.rows.clear();
+        self.used.clear();
+    }
+}
+
+// the text a replace session searches: the document flattened once when the
+// session starts. Replacements made since move the text after them, which is
+// tracked as a byte shift instead of flattening the document again
+pub struct Snapshot {
+    text: String,
+    shift: isize,
+}
+
+impl Snapshot {
+    fn document_byte(&self, byte: usize) -> usize {
+        (byte as isize + self.shift) as usize
+    }
+}
+
+// the text is kept in a rope where every row, including the last one, ends
+// with a '\n', so an empty document is an empty rope. Files over the large
+// file threshold are read from a memory map instead and are read-only.
+// Row views handed out are cached so that their grapheme index is built once
+// and patched in place by single-character edits
 #[derive(Default)]
 pub struct Document {
-    rows: Vec<Row>,
+    text: Rope,
+    large_file: Option<LargeFile>,
+    row_cache: RefCell<RowCache>,
+    bracket_cache: RefCell<Option<(usize, usize, usize, Option<Position>)>>, //revision, x, y and the bracket found there
     pub file_name: Option<String>,
     dirty: bool,
+    revision: usize, //bumped by every edit
+    file_type: FileType,
+    history: History,
+    disk: Option<DiskState>, //the file as it was last read or written
+    encoding: Option<&'static Encoding>, //none for UTF-8
+    read_only: bool,
 }
 
 impl Document {
     pub fn open(filename: &str) -> Result<Self, std::io::Error> {
-        let file_contents = fs::read_to_string(filename)?;
-        let mut rows = Vec::new();
+        Self::open_with(filename, None)
+    }
+
+    pub fn open_with(filename: &str, encoding: Option<&'static Encoding>) -> Result<Self, std::io::Error> {
+        let bytes = fs::read(filename)?;
+        let disk = DiskState::new(filename, [&bytes[..]]);
+        let file_contents = decode(bytes, encoding)?;
+        let mut contents = String::with_capacity(file_contents.len().saturating_add(1));
         for line in file_contents.lines() {
-            rows.push(Row::from(line));
+            contents.push_str(line);
+            contents.push('\n');
         }
-        let file_name: Option<String>;
-        let last_slash_idx = filename.to_string().rfind('\\');
-        if let Some(idx) = last_slash_idx {
-            file_name = Some(filename[idx+1..].to_string());
+        let file_name = Self::base_name(filename);
+        let file_type = FileType::from(&file_name);
+
+        Ok(Self {
+            text: Rope::from_str(&contents),
+            large_file: None,
+            row_cache: RefCell::default(),
+            bracket_cache: RefCell::default(),
+            file_name: Some(file_name),
+            dirty: false,
+            revision: 0,
+            file_type,
+            history: History::default(),
+            disk,
+            encoding,
+            read_only: !is_writable(filename),
+        })
+    }
+
+    pub fn open_large(filename: &str) -> Result<Self, std::io::Error> {
+        let large_file = LargeFile::open(filename)?;
+        let file_name = Self::base_name(filename);
+        let file_type = FileType::from(&file_name);
+        Ok(Self {
+            text: Rope::new(),
+            large_file: Some(large_file),
+            row_cache: RefCell::default(),
+            bracket_cache: RefCell::default(),
+            file_name: Some(file_name),
+            dirty: false,
+            revision: 0,
+            file_type,
+            history: History::default(),
+            disk: DiskState::unhashed(filename),
+            encoding: None,
+            read_only: false,
+        })
+    }
+
+    // an unnamed document holding `contents`, used for views such as diffs
+    pub fn from_text(contents: &str) -> Self {
+        let mut text = String::with_capacity(contents.len().saturating_add(1));
+        for line in contents.lines() {
+            text.push_str(line);
+            text.push('\n');
         }
-        else {
-            file_name = Some(filename.to_string());
+        Self {
+            text: Rope::from_str(&text),
+            ..Self::default()
         }
-        
+    }
+
+    pub fn from_reader<R: Read>(mut reader: R, encoding: Option<&'static Encoding>) -> Result<Self, std::io::Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
         Ok(Self {
-            rows,
-            file_name,
-            dirty: false,
+            encoding,
+            ..Self::from_text(&decode(bytes, encoding)?)
         })
     }
 
-    pub fn get_row(&self, idx: usize) -> Option<&Row> {
-        self.rows.get(idx)
+    // an empty document for a file that does not exist yet
+    pub fn new_file(filename: &str) -> Self {
+        let file_name = Self::base_name(filename);
+        Self {
+            file_type: FileType::from(&file_name),
+            file_name: Some(file_name),
+            ..Self::default()
+        }
+    }
+
+    fn base_name(filename: &str) -> String {
+        let last_slash_idx = filename.rfind('\\');
+        if let Some(idx) = last_slash_idx {
+            filename[idx+1..].to_string()
+        }
+        else {
+            filename.to_string()
+        }
+    }
+
+    pub fn get_row(&self, idx: usize) -> Option<Rc<Row>> {
+        if idx >= self.get_row_num() {
+            return None;
+        }
+        let mut cache = self.row_cache.borrow_mut();
+        if let Some(row) = cache.get(idx) {
+            return Some(row);
+        }
+        let row = Rc::new(Row::from(&self.row_text(idx)[..]));
+        cache.insert(idx, Rc::clone(&row));
+        Some(row)
+    }
+
+    pub fn file_type(&self) -> &FileType {
+        &self.file_type
     }
 
     pub fn is_empty(&self) -> bool {
-        self.rows.is_empty() && self.file_name.is_none()
+        self.get_row_num() == 0 && self.file_name.is_none()
     }
 
     pub fn get_row_num(&self) -> usize {
-        self.rows.len()
+        match &self.large_file {
+            Some(large_file) => large_file.len(),
+            None => self.text.len_lines().saturating_sub(1),
+        }
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only || self.large_file.is_some()
+    }
+
+    // false for large files, which stay read-only whatever the flag says
+    pub fn can_edit(&self) -> bool {
+        self.large_file.is_none()
+    }
+
+    pub fn set_read_only(&mut self, read_only: bool) {
+        self.read_only = read_only;
+    }
+
+    pub fn encoding(&self) -> Option<&'static Encoding> {
+        self.encoding
+    }
+
+    pub fn is_indexing(&self) -> bool {
+        self.large_file.as_ref().map_or(false, LargeFile::is_indexing)
+    }
+
+    pub fn index_progress(&self) -> usize {
+        self.large_file.as_ref().map_or(100, LargeFile::progress)
+    }
+
+    // when indexing started, while it is still running
+    pub fn indexing_since(&self) -> Option<Instant> {
+        self.large_file.as_ref().filter(|large_file| large_file.is_indexing()).map(LargeFile::started)
     }
 
     pub fn insert(&mut self, at: &Position, c:char) {
         let len = self.get_row_num();
-        if at.y > len {
+        if at.y > len || self.is_read_only() {
             return;
         }
-        self.dirty = true;
+        self.changed();
+        let before = self.row_texts(at.y, at.y.saturating_add(1));
         if c == '\n'{
             self.insert_new_line(at);
         }
         else if at.y == len {
-            let mut row = Row::default();
-            row.insert(0, c);
-            self.rows.push(row);
+            let end = self.text.len_chars();
+            self.text.insert(end, &format!("{}\n", c));
         }
         else {
-            let row = &mut self.rows[at.y];
-            row.insert(at.x, c);
+            let idx = self.char_index(at);
+            self.text.insert_char(idx, c);
+            if let Some(row) = self.row_cache.get_mut().get_mut(at.y) {
+                row.insert(at.x, c);
+            }
         }
+        self.record(at, before, len);
     }
 
     pub fn delete(&mut self, at: &Position) {
         let len = self.get_row_num();
-        if at.y < len {
-            self.dirty = true;
-            if at.x == self.rows[at.y].len() && (at.y + 1) < len {
-                let next_row = self.rows.remove(at.y+1);
-                let row = &mut self.rows[at.y];
-                row.append(&next_row);
+        if self.is_read_only() {
+            return;
+        }
+        if let Some(row) = self.get_row(at.y) {
+            let joins = at.x == row.len() && (at.y + 1) < len;
+            if at.x >= row.len() && !joins {
+                return;
+            }
+            self.changed();
+            let before = self.row_texts(at.y, if joins { at.y + 2 } else { at.y + 1 });
+            let idx = self.char_index(at);
+            if joins {
+                self.text.remove(idx..idx.saturating_add(1));
+                self.row_cache.get_mut().clear();
             }
             else {
-                let row = &mut self.rows[at.y];
-                row.delete(at.x);
+                let end = self.char_index(&Position {x: at.x.saturating_add(1), y: at.y});
+                self.text.remove(idx..end);
+                if let Some(row) = self.row_cache.get_mut().get_mut(at.y) {
+                    row.delete(at.x);
+                }
             }
+            self.record(at, before, len);
         }
     }
 
     pub fn insert_new_line(&mut self, at: &Position) {
         let len = self.get_row_num();
-        if at.y <= len {
-            if at.y == len {
-                self.rows.push(Row::default());
+        if at.y <= len && !self.is_read_only() {
+            let idx = self.char_index(at);
+            self.text.insert_char(idx, '\n');
+            self.row_cache.get_mut().clear();
+        }
+    }
+
+    // position of the bracket matching the one at `at`; it is looked up for
+    // every frame drawn, so the last answer is kept until the cursor moves or
+    // the text changes
+    pub fn matching_bracket(&self, at: &Position) -> Option<Position> {
+        let key = (self.revision, at.x, at.y);
+        if let Some((revision, x, y, bracket)) = &*self.bracket_cache.borrow() {
+            if (*revision, *x, *y) == key {
+                return bracket.clone();
             }
-            else if at.x == self.rows[at.y].len() {
-                if at.y.saturating_add(1) == len {
-                    self.rows.push(Row::default());
+        }
+        let bracket = self.find_matching_bracket(at);
+        // rows still being indexed may hold the match later on
+        if !self.is_indexing() {
+            *self.bracket_cache.borrow_mut() = Some((key.0, key.1, key.2, bracket.clone()));
+        }
+        bracket
+    }
+
+    fn find_matching_bracket(&self, at: &Position) -> Option<Position> {
+        let bracket = self.get_row(at.y)?.slice(at.x, at.x.saturating_add(1));
+        let (open, close) = BRACKETS
+            .iter()
+            .find(|(open, close)| bracket == *open || bracket == *close)?;
+        let forward = bracket == *open;
+        let mut depth: usize = 0;
+        let rows: Box<dyn Iterator<Item = usize>> = if forward {
+            Box::new(at.y..self.get_row_num())
+        }
+        else {
+            Box::new((0..=at.y).rev())
+        };
+        let rows = rows.take(MAX_BRACKET_SCAN_ROWS);
+        for y in rows {
+            let text = self.row_text(y);
+            let mut graphemes: Vec<(usize, &str)> = text.graphemes(true).enumerate().collect();
+            if y == at.y {
+                if forward {
+                    graphemes.drain(..at.x);
                 }
                 else {
-                    self.rows.insert(at.y + 1, Row::default());
+                    graphemes.truncate(at.x.saturating_add(1));
+                }
+            }
+            if !forward {
+                graphemes.reverse();
+            }
+            for (x, grapheme) in graphemes {
+                if grapheme == bracket {
+                    depth += 1;
+                }
+                else if grapheme == *open || grapheme == *close {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Some(Position {x, y});
+                    }
                 }
             }
+        }
+        None
+    }
+
+    // replaces rows [start, end) with `rows` as a single undo step
+    pub fn replace_rows(&mut self, start: usize, end: usize, rows: Vec<String>, at: &Position) {
+        let len = self.get_row_num();
+        let end = cmp::min(end, len);
+        if start > end || (start == end && rows.is_empty()) || self.is_read_only() {
+            return;
+        }
+        let before = self.row_texts(start, end);
+        self.splice_rows(start, end - start, &rows);
+        self.history.push(Change {
+            start,
+            before,
+            after: rows,
+            cursor: at.clone(),
+        });
+        self.history.seal();
+        self.changed();
+    }
+
+    pub fn duplicate_rows(&mut self, start: usize, end: usize, at: &Position) {
+        let mut rows = self.row_texts(start, end);
+        rows.extend(rows.clone());
+        self.replace_rows(start, end, rows, at);
+    }
+
+    pub fn delete_rows(&mut self, start: usize, end: usize, at: &Position) {
+        self.replace_rows(start, end, Vec::new(), at);
+    }
+
+    // joins rows [start, end) (or `start` with the next row when the range is a
+    // single row) replacing the whitespace around each join with one space;
+    // returns the column of the last join
+    pub fn join_rows(&mut self, start: usize, end: usize, at: &Position) -> Option<usize> {
+        let end = cmp::max(end, start.saturating_add(2));
+        let mut texts = self.row_texts(start, end).into_iter();
+        let mut joined = texts.next()?;
+        let mut column = None;
+        for text in texts {
+            joined.truncate(joined.trim_end().len());
+            let text = text.trim_start();
+            if !joined.is_empty() && !text.is_empty() {
+                joined.push(' ');
+            }
+            column = Some(joined.graphemes(true).count());
+            joined.push_str(text);
+        }
+        if column.is_some() {
+            self.replace_rows(start, end, vec![joined], at);
+        }
+        column
+    }
+
+    // moves rows [start, end) one row up or down; false at the document edge
+    pub fn move_rows(&mut self, start: usize, end: usize, up: bool, at: &Position) -> bool {
+        let end = cmp::min(end, self.get_row_num());
+        if start >= end {
+            return false;
+        }
+        let mut rows = self.row_texts(start, end);
+        if up {
+            if start == 0 {
+                return false;
+            }
+            rows.extend(self.row_texts(start - 1, start));
+            self.replace_rows(start - 1, end, rows, at);
+        }
+        else {
+            if end >= self.get_row_num() {
+                return false;
+            }
+            rows.splice(0..0, self.row_texts(end, end.saturating_add(1)));
+            self.replace_rows(start, end.saturating_add(1), rows, at);
+        }
+        true
+    }
+
+    // deletes graphemes [start, end) of row `y` as a single undo step
+    pub fn delete_in_row(&mut self, y: usize, start: usize, end: usize, at: &Position) {
+        if let Some(row) = self.get_row(y) {
+            let text = format!("{}{}", row.slice(0, start), row.slice(end, row.len()));
+            self.replace_rows(y, y.saturating_add(1), vec![text], at);
+        }
+    }
+
+    pub fn indent_rows(&mut self, start: usize, end: usize, width: usize, at: &Position) {
+        let unit = self.file_type.indent_rules().unit(width);
+        let rows = self.row_texts(start, end).into_iter().map(|text| {
+            if text.is_empty() {
+                text
+            }
+            else {
+                format!("{}{}", unit, text)
+            }
+        }).collect();
+        self.replace_rows(start, end, rows, at);
+    }
+
+    pub fn outdent_rows(&mut self, start: usize, end: usize, width: usize, at: &Position) {
+        let rows = self.row_texts(start, end).into_iter().map(|text| {
+            if let Some(rest) = text.strip_prefix('\t') {
+                return rest.to_string();
+            }
+            let spaces = text.len() - text.trim_start_matches(' ').len();
+            text[cmp::min(spaces, width)..].to_string()
+        }).collect();
+        self.replace_rows(start, end, rows, at);
+    }
+
+    // comments out rows [start, end) with the filetype's line comment token,
+    // or uncomments them if every non-blank row is already commented
+    pub fn toggle_comment(&mut self, start: usize, end: usize, at: &Position) -> bool {
+        let token = match self.file_type.comment() {
+            Some(token) => token.to_string(),
+            None => return false,
+        };
+        let texts = self.row_texts(start, end);
+        let mut non_blank = texts.iter().filter(|text| !text.trim().is_empty()).peekable();
+        if non_blank.peek().is_none() {
+            return true;
+        }
+        // only spaces and tabs count as indentation, as in `Row::indentation`,
+        // so that the shared indent is a char boundary on every row
+        let content = |text: &str| text.trim_start_matches([' ', '\t']).len();
+        let commented = non_blank.clone().all(|text| text[text.len() - content(text)..].starts_with(&token));
+        let indent = non_blank
+            .map(|text| text.len() - content(text))
+            .min()
+            .unwrap_or(0);
+        let rows = texts.into_iter().map(|text| {
+            if text.trim().is_empty() {
+                return text;
+            }
+            let (leading, content) = text.split_at(text.len() - content(&text));
+            if commented {
+                let rest = &content[token.len()..];
+                format!("{}{}", leading, rest.strip_prefix(' ').unwrap_or(rest))
+            }
             else {
-                let row = &mut self.rows[at.y];
-                let next_row = row.split(at.x);
-                self.rows.insert(at.y + 1, next_row);
+                format!("{}{} {}", &text[..indent], token, &text[indent..])
             }
+        }).collect();
+        self.replace_rows(start, end, rows, at);
+        true
+    }
+
+    // the match of `search` that follows `at` (or, going backwards, the last
+    // one starting before it) within `scope`, or the whole document, wrapping
+    // around the ends of it
+    pub fn find(
+        &self,
+        search: &Search,
+        at: &Position,
+        forward: bool,
+        scope: Option<&(Position, Position)>,
+    ) -> Option<(Position, Position)> {
+        let text = self.text_view()?;
+        let (low, high) = scope.map_or((0, text.len()), |(start, end)| (self.byte_of(start), self.byte_of(end)));
+        let from = cmp::min(cmp::max(self.byte_of(at), low), high);
+        let found = if forward {
+            search
+                .matches(&text, from, high)
+                .find(|(start, end)| *start > from || end > start)
+                .or_else(|| search.matches(&text, low, high).next())
         }
+        else {
+            search
+                .matches(&text, low, high)
+                .take_while(|(start, _)| *start < from)
+                .last()
+                .or_else(|| search.matches(&text, from, high).last())
+        };
+        found.map(|(start, end)| (self.position_of(start), self.position_of(end)))
+    }
+
+    // the text for a replace session; None for a large file that is not valid
+    // UTF-8
+    pub fn snapshot(&self) -> Option<Snapshot> {
+        Some(Snapshot {
+            text: self.text_view()?.into_owned(),
+            shift: 0,
+        })
+    }
+
+    // the first match of `search` in `snapshot` after `at`, which has to lie
+    // past the replacements made since it was taken, within `scope` or the
+    // whole document; unlike `find` it does not wrap around
+    pub fn find_in(
+        &self,
+        snapshot: &Snapshot,
+        search: &Search,
+        at: &Position,
+        scope: Option<&(Position, Position)>,
+    ) -> Option<(Position, Position)> {
+        // the scope starts before every replacement made so far, so the shift
+        // does not apply to it
+        let (low, high) = scope.map_or((0, snapshot.text.len()), |(start, end)| {
+            (self.byte_of(start), self.snapshot_byte(snapshot, end))
+        });
+        let from = cmp::min(cmp::max(self.snapshot_byte(snapshot, at), low), high);
+        let (start, end) = search
+            .matches(&snapshot.text, from, high)
+            .find(|(start, end)| *start > from || end > start)?;
+        Some((
+            self.position_of(snapshot.document_byte(start)),
+            self.position_of(snapshot.document_byte(end)),
+        ))
+    }
+
+    // replaces up to `limit` matches of `search` in `snapshot` between `from`
+    // and `to`, or the end of the document, as a single undo step; returns how
+    // many were replaced and where the last replacement ends
+    pub fn replace(
+        &mut self,
+        snapshot: &mut Snapshot,
+        search: &Search,
+        replacement: &str,
+        from: &Position,
+        to: Option<&Position>,
+        limit: Option<usize>,
+    ) -> (usize, Position) {
+        if self.is_read_only() {
+            return (0, from.clone());
+        }
+        let to = to.map_or(snapshot.text.len(), |to| self.snapshot_byte(snapshot, to));
+        let replaced = match search.replace(&snapshot.text, replacement, self.snapshot_byte(snapshot, from), to, limit) {
+            Some(replaced) => replaced,
+            None => return (0, from.clone()),
+        };
+        let start = snapshot.document_byte(replaced.start);
+        self.replace_bytes(start, snapshot.document_byte(replaced.end), &replaced.text, from);
+        snapshot.shift += replaced.text.len() as isize - (replaced.end - replaced.start) as isize;
+        (replaced.count, self.position_of(start + replaced.cursor))
+    }
+
+    // replaces bytes [start, end) of the text with `text`, rewriting only the
+    // rows they lie on
+    fn replace_bytes(&mut self, start: usize, end: usize, text: &str, at: &Position) {
+        let first = self.line_of(start);
+        let region_start = self.line_byte(first);
+        let mut last = self.line_of(end);
+        if end > region_start && end == self.line_byte(last) {
+            last = last.saturating_sub(1);
+        }
+        let mut region_end = self.line_byte(last.saturating_add(1));
+        let mut region = format!(
+            "{}{}{}",
+            self.text.byte_slice(region_start..start),
+            text,
+            self.text.byte_slice(end..region_end),
+        );
+        // a replacement that drops the last line break joins the next row on
+        while !region.is_empty() && !region.ends_with('\n') && region_end < self.text.len_bytes() {
+            last += 1;
+            let next = self.line_byte(last.saturating_add(1));
+            region.push_str(&self.text.byte_slice(region_end..next).to_string());
+            region_end = next;
+        }
+        let rows = region.split_terminator('\n').map(String::from).collect();
+        self.replace_rows(first, last.saturating_add(1), rows, at);
+    }
+
+    fn snapshot_byte(&self, snapshot: &Snapshot, at: &Position) -> usize {
+        (self.byte_of(at) as isize - snapshot.shift) as usize
+    }
+
+    // whether the text can be searched, which a large file that is not valid
+    // UTF-8 cannot
+    pub fn is_searchable(&self) -> bool {
+        self.large_file.as_ref().map_or(true, |large_file| large_file.text().is_some())
+    }
+
+    // the whole text as one string, for searches that cross rows; None for a
+    // large file that is not valid UTF-8
+    fn text_view(&self) -> Option<Cow<'_, str>> {
+        match &self.large_file {
+            Some(large_file) => large_file.text().map(Cow::from),
+            None => Some(Cow::from(self.text.slice(..))),
+        }
+    }
+
+    // byte offset where row `y` starts, or the text length past the last row
+    fn line_byte(&self, y: usize) -> usize {
+        match &self.large_file {
+            Some(large_file) => large_file.line_start(y),
+            None => self.text.line_to_byte(cmp::min(y, self.get_row_num())),
+        }
+    }
+
+    fn line_of(&self, byte: usize) -> usize {
+        match &self.large_file {
+            Some(large_file) => large_file.line_of(byte),
+            None => self.text.byte_to_line(cmp::min(byte, self.text.len_bytes())),
+        }
+    }
+
+    fn byte_of(&self, at: &Position) -> usize {
+        let row_byte = self.get_row(at.y).map_or(0, |row| row.byte_index(at.x));
+        self.line_byte(at.y) + row_byte
+    }
+
+    // the position of byte `byte`; the end of the text is the end of the last row
+    fn position_of(&self, byte: usize) -> Position {
+        let y = self.line_of(byte);
+        if y >= self.get_row_num() {
+            let y = self.get_row_num().saturating_sub(1);
+            return Position {
+                x: self.get_row(y).map_or(0, |row| row.len()),
+                y,
+            };
+        }
+        let x = self.get_row(y).map_or(0, |row| row.grapheme_index(byte - self.line_byte(y)));
+        Position {x, y}
+    }
+
+    // the edits made until `end_group` are undone as one step
+    pub fn begin_group(&mut self) {
+        self.history.begin_group();
+    }
+
+    pub fn end_group(&mut self) {
+        self.history.end_group();
+    }
+
+    pub fn undo(&mut self) -> Option<Position> {
+        if self.is_read_only() {
+            return None;
+        }
+        let step = self.history.pop_undo()?;
+        let mut cursor = None;
+        for change in step.iter().rev() {
+            self.splice_rows(change.start, change.after.len(), &change.before);
+            cursor = Some(change.cursor.clone());
+        }
+        self.history.push_redo(step);
+        self.changed();
+        self.dirty = !self.history.is_saved();
+        cursor
+    }
+
+    pub fn redo(&mut self) -> Option<Position> {
+        if self.is_read_only() {
+            return None;
+        }
+        let step = self.history.pop_redo()?;
+        let mut cursor = None;
+        for change in &step {
+            self.splice_rows(change.start, change.before.len(), &change.after);
+            cursor = Some(change.cursor.clone());
+        }
+        self.history.push_undo(step);
+        self.changed();
+        self.dirty = !self.history.is_saved();
+        cursor
+    }
+
+    fn splice_rows(&mut self, start: usize, count: usize, texts: &[String]) {
+        let len = self.get_row_num();
+        let from = self.line_start(cmp::min(start, len));
+        let to = self.line_start(cmp::min(start.saturating_add(count), len));
+        self.text.remove(from..to);
+        let inserted: String = texts.iter().map(|text| format!("{}\n", text)).collect();
+        self.text.insert(from, &inserted);
+        self.row_cache.get_mut().clear();
+    }
+
+    fn row_texts(&self, start: usize, end: usize) -> Vec<String> {
+        let end = cmp::min(end, self.get_row_num());
+        let start = cmp::min(start, end);
+        (start..end).map(|y| self.row_text(y)).collect()
+    }
+
+    fn row_text(&self, y: usize) -> String {
+        if let Some(large_file) = &self.large_file {
+            return large_file.row_text(y).unwrap_or_default();
+        }
+        let mut text = String::from(self.text.line(y));
+        if text.ends_with('\n') {
+            text.pop();
+        }
+        text
+    }
+
+    fn line_start(&self, y: usize) -> usize {
+        self.text.line_to_char(y)
+    }
+
+    // char index in the rope of the grapheme at `at`, clamped to the row end
+    fn char_index(&self, at: &Position) -> usize {
+        match self.get_row(at.y) {
+            Some(row) => {
+                let byte = self.text.line_to_byte(at.y) + row.byte_index(at.x);
+                self.text.byte_to_char(byte)
+            },
+            None => self.text.len_chars(),
+        }
+    }
+
+    // records the rows touched by a primitive edit at `at`, given the rows
+    // it replaced and the document length before the edit
+    fn record(&mut self, at: &Position, before: Vec<String>, old_len: usize) {
+        let after_len = (before.len() + self.get_row_num()).saturating_sub(old_len);
+        let after = self.row_texts(at.y, at.y.saturating_add(after_len));
+        self.history.push(Change {
+            start: at.y,
+            before,
+            after,
+            cursor: at.clone(),
+        });
+    }
+
+    // leading whitespace for the row created by pressing Enter at `at`
+    pub fn new_line_indent(&self, at: &Position, width: usize) -> String {
+        let row = match self.get_row(at.y) {
+            Some(row) => row,
+            None => return String::new(),
+        };
+        let indentation = row.indentation();
+        let mut indent = indentation[..cmp::min(indentation.len(), at.x)].to_string();
+        let rules = self.file_type.indent_rules();
+        let before = row.slice(0, at.x);
+        if let Some(last) = before.trim_end().chars().last() {
+            let after = row.slice(at.x, row.len());
+            let closes = after.trim_start().starts_with(|c| rules.is_closer(c));
+            if rules.is_opener(last) && !closes {
+                indent.push_str(&rules.unit(width));
+            }
+        }
+        indent
+    }
+
+    // number of graphemes to remove before `at` to go back one indent level,
+    // 0 if the cursor is not inside the leading whitespace
+    pub fn unindent_width(&self, at: &Position, width: usize) -> usize {
+        let row = match self.get_row(at.y) {
+            Some(row) => row,
+            None => return 0,
+        };
+        let indentation = row.indentation();
+        if at.x == 0 || at.x > indentation.len() {
+            return 0;
+        }
+        let before = &indentation[..at.x];
+        if before.ends_with('\t') {
+            return 1;
+        }
+        let spaces = before.len() - before.trim_end_matches(' ').len();
+        let level = if at.x % width == 0 { width } else { at.x % width };
+        cmp::min(level, spaces)
     }
 
     pub fn is_dirty(&self) -> bool {
         self.dirty
     }
 
+    // whether someone else wrote to the file since it was opened or saved
+    pub fn is_changed_on_disk(&mut self) -> bool {
+        match (&self.file_name, &mut self.disk) {
+            (Some(file_name), Some(disk)) => disk.is_changed(file_name),
+            _ => false,
+        }
+    }
+
+    pub fn revision(&self) -> usize {
+        self.revision
+    }
+
+    pub fn contents(&self) -> String {
+        self.text.to_string()
+    }
+
+    fn changed(&mut self) {
+        self.dirty = true;
+        self.revision = self.revision.wrapping_add(1);
+    }
+
     pub fn save(&mut self) -> Result<(), std::io::Error>  {
         if self.dirty == true {
-            if let Some(file_name) = &self.file_name {
-                let mut file = fs::File::create(file_name)?;
-                for row in &self.rows {
-                    file.write_all(row.as_bytes())?;
-                    file.write_all(b"\n")?;
-                }
-                self.dirty = false;
+            self.write()?;
+        }
+        Ok(())
+    }
+
+    // follows the file to `file_name` after it was renamed on disk
+    pub fn moved_to(&mut self, file_name: &str) {
+        self.file_name = Some(file_name.to_string());
+        self.file_type = FileType::from(file_name);
+    }
+
+    // the file was deleted, so the text is only kept here until it is saved
+    // again
+    pub fn file_deleted(&mut self) {
+        self.disk = None;
+        self.history.mark_unsaved();
+        self.dirty = true;
+    }
+
+    // writes the document to `filename`, which becomes its file name; the
+    // buffer is no longer read-only even if it was for the old one
+    pub fn save_as(&mut self, filename: &str) -> Result<(), std::io::Error> {
+        self.file_name = Some(filename.to_string());
+        self.write()?;
+        self.read_only = false;
+        Ok(())
+    }
+
+    // the text as it is written to disk, in the document's encoding
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let text = self.text.to_string();
+        match self.encoding {
+            Some(encoding) if encoding != UTF_8 => encoding.encode(&text).0.into_owned(),
+            _ => text.into_bytes(),
+        }
+    }
+
+    // records that `bytes` were written to the file by other means
+    pub fn mark_saved(&mut self, bytes: &[u8]) {
+        if let Some(file_name) = &self.file_name {
+            self.disk = DiskState::new(file_name, [bytes]);
+        }
+        self.history.mark_saved();
+        self.dirty = false;
+    }
+
+    fn write(&mut self) -> Result<(), std::io::Error> {
+        if self.large_file.is_some() {
+            return Err(std::io::Error::new(std::io::ErrorKind::Other, "large files cannot be saved"));
+        }
+        if let Some(file_name) = &self.file_name {
+            self.file_type = FileType::from(file_name);
+            let mut writer = BufWriter::new(fs::File::create(file_name)?);
+            // errors such as a full disk may only show up when the buffer is
+            // flushed or the file synced, and must not pass as a save
+            match self.encoding {
+                Some(encoding) if encoding != UTF_8 => {
+                    let bytes = self.to_bytes();
+                    writer.write_all(&bytes)?;
+                    writer.flush()?;
+                    writer.get_ref().sync_all()?;
+                    self.disk = DiskState::new(file_name, [&bytes[..]]);
+                },
+                _ => {
+                    self.text.write_to(&mut writer)?;
+                    writer.flush()?;
+                    writer.get_ref().sync_all()?;
+                    self.disk = DiskState::new(file_name, self.text.chunks().map(str::as_bytes));
+                },
             }
+            self.history.mark_saved();
+            self.dirty = false;
         }
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+// text that is not valid UTF-8 is refused unless an encoding was asked for
+fn decode(bytes: Vec<u8>, encoding: Option<&'static Encoding>) -> Result<String, std::io::Error> {
+    match encoding {
+        Some(encoding) => Ok(encoding.decode(&bytes).0.into_owned()),
+        None => String::from_utf8(bytes)
+            .map_err(|err| std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("not valid UTF-8 ({}), see --encoding", err),
+            )),
+    }
+}
+
+// opening for writing without truncating leaves the file untouched
+fn is_writable(filename: &str) -> bool {
+    fs::OpenOptions::new().write(true).open(filename).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::search::SearchOptions;
+
+    fn document(text: &str) -> Document {
+        Document::from_text(text)
+    }
+
+    #[test]
+    fn new_rows_are_indented_after_openers() {
+        let mut rust = document("fn main() {\n    call(1,\nif x {}\n");
+        rust.file_type = FileType::from("main.rs");
+        assert_eq!(rust.new_line_indent(&Position {x: 11, y: 0}, 4), "    ");
+        assert_eq!(rust.new_line_indent(&Position {x: 12, y: 1}, 4), "    ");
+        assert_eq!(rust.new_line_indent(&Position {x: 2, y: 1}, 4), "  ");
+        assert_eq!(rust.new_line_indent(&Position {x: 6, y: 2}, 4), "");
+        let mut make = document("all:\n");
+        make.file_type = FileType::from("Makefile");
+        assert_eq!(make.new_line_indent(&Position {x: 4, y: 0}, 4), "\t");
+        assert_eq!(document("{\n").new_line_indent(&Position {x: 1, y: 0}, 4), "");
+    }
+
+    #[test]
+    fn unindenting_goes_back_to_the_previous_level() {
+        let document = document("        x\n\t\ty\n  z\n");
+        assert_eq!(document.unindent_width(&Position {x: 8, y: 0}, 4), 4);
+        assert_eq!(document.unindent_width(&Position {x: 6, y: 0}, 4), 2);
+        assert_eq!(document.unindent_width(&Position {x: 0, y: 0}, 4), 0);
+        assert_eq!(document.unindent_width(&Position {x: 9, y: 0}, 4), 0);
+        assert_eq!(document.unindent_width(&Position {x: 2, y: 1}, 4), 1);
+        assert_eq!(document.unindent_width(&Position {x: 2, y: 2}, 4), 2);
+    }
+
+    #[test]
+    fn brackets_are_matched_across_rows_and_nesting() {
+        let mut document = document("fn f(a: [u8; 2]) {\n    g(a);\n}\n");
+        let at = |x, y| Position {x, y};
+        let found = |document: &Document, x, y| document.matching_bracket(&at(x, y)).map(|p| (p.x, p.y));
+        assert_eq!(found(&document, 4, 0), Some((15, 0)));
+        assert_eq!(found(&document, 15, 0), Some((4, 0)));
+        assert_eq!(found(&document, 8, 0), Some((14, 0)));
+        assert_eq!(found(&document, 17, 0), Some((0, 2)));
+        assert_eq!(found(&document, 0, 2), Some((17, 0)));
+        assert_eq!(found(&document, 0, 0), None);
+        // the cached answer is dropped once the text changes
+        document.delete(&at(0, 2));
+        assert_eq!(found(&document, 17, 0), None);
+        assert_eq!(found(&document, 5, 1), Some((7, 1)));
+    }
+
+    #[test]
+    fn rows_are_duplicated_deleted_and_moved() {
+        let mut document = document("a\nb\nc\n");
+        let at = Position::default();
+        document.duplicate_rows(0, 2, &at);
+        assert_eq!(document.contents(), "a\nb\na\nb\nc\n");
+        document.delete_rows(1, 3, &at);
+        assert_eq!(document.contents(), "a\nb\nc\n");
+        assert!(document.move_rows(1, 3, true, &at));
+        assert_eq!(document.contents(), "b\nc\na\n");
+        assert!(!document.move_rows(0, 1, true, &at));
+        assert!(document.move_rows(0, 1, false, &at));
+        assert_eq!(document.contents(), "c\nb\na\n");
+        assert!(!document.move_rows(2, 3, false, &at));
+        // each operation is one undo step
+        document.undo();
+        document.undo();
+        assert_eq!(document.contents(), "a\nb\nc\n");
+    }
+
+    #[test]
+    fn joined_rows_are_separated_by_one_space() {
+        let mut document = document("one  \n   two\n\nthree\nfour\n");
+        let at = Position::default();
+        assert_eq!(document.join_rows(0, 1, &at), Some(4));
+        assert_eq!(document.contents(), "one two\n\nthree\nfour\n");
+        assert_eq!(document.join_rows(0, 3, &at), Some(8));
+        assert_eq!(document.contents(), "one two three\nfour\n");
+        assert_eq!(document.join_rows(1, 2, &at), None);
+        assert_eq!(document.contents(), "one two three\nfour\n");
+    }
+
+    #[test]
+    fn comments_go_after_the_shared_indentation() {
+        let mut document = document("  a\n\u{3000}b\n \u{a0}c\n");
+        document.file_type = FileType::from("main.rs");
+        let at = Position::default();
+        assert!(document.toggle_comment(0, 3, &at));
+        assert_eq!(document.contents(), "//   a\n// \u{3000}b\n//  \u{a0}c\n");
+        assert!(document.toggle_comment(0, 3, &at));
+        assert_eq!(document.contents(), "  a\n\u{3000}b\n \u{a0}c\n");
+    }
+
+    #[test]
+    fn row_cache_drops_the_least_recently_used_row() {
+        let mut cache = RowCache::default();
+        for idx in 0..ROW_CACHE_SIZE {
+            cache.insert(idx, Rc::new(Row::from("row")));
+        }
+        assert!(cache.get(0).is_some());
+        cache.insert(ROW_CACHE_SIZE, Rc::new(Row::from("row")));
+        assert!(cache.get(0).is_some());
+        assert!(cache.get(1).is_none());
+        assert_eq!(cache.rows.len(), ROW_CACHE_SIZE);
+    }
+
+    #[test]
+    fn rows_handed_out_are_not_changed_by_later_edits() {
+        let mut document = document("abc\n");
+        let row = document.get_row(0).unwrap();
+        document.insert(&Position {x: 1, y: 0}, 'x');
+        assert_eq!(row.as_str(), "abc");
+        assert_eq!(document.get_row(0).unwrap().as_str(), "axbc");
+    }
+
+    #[test]
+    fn replacements_from_one_snapshot_follow_earlier_ones() {
+        let mut document = document("a-b\nc-d\ne-f\n");
+        let search = SearchOptions::default().compile("-").unwrap();
+        let mut snapshot = document.snapshot().unwrap();
+        let (start, _) = document.find_in(&snapshot, &search, &Position::default(), None).unwrap();
+        let (_, at) = document.replace(&mut snapshot, &search, " <-> ", &start, None, Some(1));
+        let (start, end) = document.find_in(&snapshot, &search, &at, None).unwrap();
+        assert_eq!((start.x, start.y, end.x, end.y), (1, 1, 2, 1));
+        let (_, at) = document.replace(&mut snapshot, &search, "\\", &start, None, Some(1));
+        let (start, _) = document.find_in(&snapshot, &search, &at, None).unwrap();
+        assert_eq!(document.replace(&mut snapshot, &search, "", &start, None, None).0, 1);
+        assert!(document.find_in(&snapshot, &search, &Position::default(), None).is_none());
+        assert_eq!(document.contents(), "a <-> b\nc\\d\nef\n");
+    }
+
+    #[test]
+    fn large_files_that_are_not_utf8_cannot_be_searched() {
+        let path = std::env::temp_dir().join(format!("hecto-latin1-{}.txt", std::process::id()));
+        fs::write(&path, b"caf\xe9\n").unwrap();
+        let document = Document::open_large(path.to_str().unwrap()).unwrap();
+        assert!(!document.is_searchable());
+        assert!(document.snapshot().is_none());
+        fs::remove_file(path).ok();
+    }
+}
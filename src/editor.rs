@@ -1,18 +1,55 @@
 use crate::Terminal;
 use crate::Document;
 use crate::Row;
+use crate::Config;
+use crate::Screen;
+use crate::swap::Swap;
+use crate::crash::{self, Signals};
+use crate::search::{Search, SearchOptions};
+use crate::grep::{self, Grep};
+use crate::finder::{self, Finder};
+use crate::browser;
+use crate::macros;
+use crate::Args;
+use std::cmp::{self, Reverse};
+use std::collections::VecDeque;
 use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::process;
 use std::time::{Duration, Instant};
 use crossterm::{
-    event::{Event, KeyModifiers, KeyCode},
+    event::{Event, KeyEvent, KeyModifiers, KeyCode},
     style::{Colors, Color},
 };
+use encoding_rs::{Encoding, UTF_8};
+use similar::TextDiff;
+use unicode_width::UnicodeWidthStr;
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 const BAR_BACKGROUND_COLOR: Color = Color::Rgb { r: 128, g: 128, b: 128 };
 const BAR_FOREGROUND_COLOR: Color = Color::Rgb { r: 255, g: 255, b: 255 };
 const TEXT_BACKGROUND_COLOR: Color = Color::Rgb { r: 245, g: 245, b: 245 };
 const TEXT_FOREGROUND_COLOR: Color = Color::Rgb { r: 0, g: 0, b: 0 };
+const BAR_COLORS: Colors = Colors {
+    foreground: Some(BAR_FOREGROUND_COLOR),
+    background: Some(BAR_BACKGROUND_COLOR),
+};
+const LINE_NUMBER_COLORS: Colors = Colors {
+    foreground: Some(Color::Rgb { r: 250, g: 128, b: 114 }),
+    background: Some(Color::Rgb { r: 240, g: 240, b: 240 }),
+};
+const BACKGROUND_POLL_INTERVAL: Duration = Duration::from_millis(100);
+const SIGNAL_POLL_INTERVAL: Duration = Duration::from_millis(250);
+const DISK_CHECK_INTERVAL: Duration = Duration::from_secs(2);
+const NOT_SEARCHABLE: &str = "Cannot search a non-UTF-8 file";
+const SELECTION_BACKGROUND_COLOR: Color = Color::Rgb { r: 173, g: 214, b: 255 };
+const BRACKET_BACKGROUND_COLOR: Color = Color::Rgb { r: 255, g: 215, b: 130 };
+const FUZZY_MATCH_COLOR: Color = Color::Rgb { r: 200, g: 40, b: 40 };
+const MAX_MACRO_RUNS: usize = 100_000;
+const CURSOR_BACKGROUND_COLOR: Color = Color::Rgb { r: 150, g: 150, b: 150 };
 
 struct StatusMessage {
     message: String,
@@ -34,53 +71,209 @@ pub struct Position {
     pub y: usize,
 }
 
+// a generated buffer whose rows lead somewhere when Enter is pressed on them
+enum Listing {
+    Grep {
+        pattern: String,
+        targets: Vec<Option<(PathBuf, Position)>>, //the file and position each row leads to
+    },
+    Directory {
+        path: PathBuf,
+        targets: Vec<Option<PathBuf>>, //the entry on each row
+    },
+}
+
+// text cut or copied; a block is pasted as a rectangle at the cursor column
+#[derive(Clone)]
+struct Clipboard {
+    rows: Vec<String>,
+    block: bool,
+}
+
+// a document in the background, together with where the user was in it
+#[derive(Default)]
+struct Buffer {
+    document: Document,
+    cursor_position: Position,
+    offset: Position,
+    mark: Option<Position>,
+    block: bool,
+    indexing: bool,
+    swap: Option<Swap>,
+    swap_revision: usize,
+    disk_warned: bool,
+    listing: Option<Listing>,
+    cursors: Vec<Position>,
+}
+
 pub struct Editor {
     if_quit: bool,
     cursor_position: Position, //position in the text document
+    cursors: Vec<Position>, //extra cursors, edited along with the main one
     offset: Position, //where the document scroll
     terminal: Terminal,
     document: Document,
     status_message: StatusMessage,
     row_num_indent: usize,
+    config: Config,
+    mark: Option<Position>, //other end of the selection
+    block: bool, //the selection is the rectangle between the mark and the cursor
+    desired_x: Option<usize>, //column kept while moving vertically
+    indexing: bool, //a large file is still being indexed in the background
+    screen: Screen,
+    swap: Option<Swap>, //swap file holding the unsaved edits, once written
+    swap_revision: usize, //document revision last written to the swap file
+    swap_time: Instant,
+    signals: Option<Signals>, //termination signals, when running in a real terminal
+    disk_check_time: Instant,
+    disk_warned: bool, //the user was told the file changed on disk
+    listing: Option<Listing>, //set when the document lists grep results or a directory
+    buffers: Vec<Buffer>, //the other open documents, in order
+    buffer_index: usize, //where the active document sits among them
+    search_options: SearchOptions,
+    last_search: Option<String>, //pattern repeated by F3 and an empty search
+    search_scope: Option<(Position, Position)>, //the selection being searched, if any
+    finder: Option<Finder>, //the file finder drawn over the text while it is open
+    grepping: Option<(Grep, String)>, //a grep running in the background, and its pattern
+    clipboard: Option<Clipboard>,
+    recording: Option<Vec<KeyEvent>>, //keys read since macro recording started
+    macro_keys: Vec<KeyEvent>, //the macro Alt-L plays
+    playback: VecDeque<KeyEvent>, //keys of the macro being played, read before the terminal
+    playing: bool,
+    macro_stop: Option<&'static str>, //why the macro being played has to stop
 }
 
 impl Editor {
-    pub fn default() -> Self {
-        let args: Vec<String> = env::args().collect();
-        let mut initial_status = String::from("HELP: Ctrl-Q = quit | Ctrl-S = save");
-        let document = if args.len() > 1 {
-            let doc = Document::open(&args[1]);
-            if let Ok(doc) = doc {
-                doc
+    // opens the files named on the command line; anything that goes wrong
+    // is reported before the terminal is switched to raw mode
+    pub fn from_args(args: &Args) -> Result<Self, String> {
+        let config = if args.no_config {
+            Config::default()
+        }
+        else if let Some(path) = &args.config {
+            Config::load_from(path).map_err(|err| format!("{}: {}", path, err))?
+        }
+        else {
+            Config::load()
+        };
+        let mut documents = Vec::new();
+        for file in &args.files {
+            if Path::new(file).is_dir() {
+                let (document, listing) = directory_listing(Path::new(file))
+                    .map_err(|err| format!("{}: {}", file, err))?;
+                documents.push((document, Some(listing)));
+                continue;
+            }
+            let document = if file == "-" {
+                Document::from_reader(io::stdin(), args.encoding)
             }
             else {
-                initial_status = format!("ERR: Could not open file: {}", &args[1]);
-                Document::default()
+                open_document(file, &config, args.encoding)
+            };
+            let mut document = document.map_err(|err| format!("{}: {}", file, err))?;
+            if args.read_only {
+                document.set_read_only(true);
             }
+            documents.push((document, None));
         }
-        else {
-            Document::default()
-        };
+        let mut documents = documents.into_iter();
+        let (document, listing) = documents.next().unwrap_or_default();
+        crash::install_panic_hook();
+        let terminal = Terminal::default(config.alternate_screen).map_err(|err| err.to_string())?;
+        let mut editor = Self::new(terminal, document, config);
+        if listing.is_some() {
+            editor.listing = listing;
+            editor.cursor_position.y = 1;
+        }
+        for (document, listing) in documents {
+            editor.push_buffer(document, listing);
+        }
+        if let Some(line) = args.line {
+            editor.cursor_position.y = cmp::min(line.saturating_sub(1), editor.document.get_row_num());
+            editor.scroll();
+        }
+        editor.signals = Some(Signals::register());
+        Ok(editor)
+    }
+
+    // an editor over `document` drawing to `terminal`, which may be headless
+    pub fn new(terminal: Terminal, document: Document, config: Config) -> Self {
         let row_num_indent = document.get_row_num().to_string().len().saturating_add(1);
+        let indexing = document.is_indexing();
+        let screen = Screen::new(
+            terminal.size.width as usize,
+            terminal.size.height as usize + 2,
+        );
         Self {
             if_quit: false,
             cursor_position: Position::default(),
+            cursors: Vec::new(),
             offset: Position::default(),
-            terminal: Terminal::default().expect("terminal default fault"),
-            //document: Document::default(),
+            terminal,
             document,
-            status_message: StatusMessage::from(initial_status),
+            status_message: StatusMessage::from(String::from("HELP: Ctrl-Q = quit | Ctrl-S = save")),
             row_num_indent,
+            config,
+            mark: None,
+            block: false,
+            desired_x: None,
+            indexing,
+            screen,
+            swap: None,
+            swap_revision: 0,
+            swap_time: Instant::now(),
+            signals: None,
+            disk_check_time: Instant::now(),
+            disk_warned: false,
+            listing: None,
+            buffers: Vec::new(),
+            buffer_index: 0,
+            search_options: SearchOptions::default(),
+            last_search: None,
+            search_scope: None,
+            finder: None,
+            grepping: None,
+            clipboard: None,
+            recording: None,
+            macro_keys: Vec::new(),
+            playback: VecDeque::new(),
+            playing: false,
+            macro_stop: None,
         }
     }
 
+    // a panic anywhere in the editor still leaves the terminal usable and the
+    // unsaved edits on disk before the error is shown
     pub fn run(&mut self) {
-        Terminal::cursor_unblink();
+        crash::own_terminal(true);
+        let result = panic::catch_unwind(AssertUnwindSafe(|| self.main_loop()));
+        crash::own_terminal(false);
+        if let Err(payload) = result {
+            let saved = self.emergency_save();
+            self.terminal.quit();
+            if let Some(message) = crash::take_panic_message() {
+                eprintln!("{}", message);
+            }
+            for path in saved {
+                eprintln!("Unsaved changes were written to {}", path.display());
+            }
+            panic::resume_unwind(payload);
+        }
+    }
+
+    fn main_loop(&mut self) {
+        self.terminal.cursor_unblink();
+        self.each_buffer(|editor| {
+            if let Err(error) = editor.check_swap() {
+                err_panic(error);
+            }
+        });
         loop {
             if let Err(error) = self.refresh_screen() {
                 err_panic(error);
             }
             if self.if_quit == true {
+                self.each_buffer(Self::remove_swap);
                 break;
             }
             if let Err(error) = self.process_keypress() {
@@ -90,80 +283,165 @@ impl Editor {
     }
 
     fn refresh_screen(&mut self) -> Result<(), std::io::Error> {
-        Terminal::cursor_hide();
-        Terminal::set_cursor_position(&Position::default());
+        self.terminal.cursor_hide();
         if self.if_quit == true {
-            Terminal::quit();
+            self.terminal.set_cursor_position(&Position::default());
+            self.terminal.quit();
         }
         else {
-            self.draw_rows();
-            self.draw_status_bar();
-            self.draw_message_bar();
-            Terminal::set_cursor_position(&Position {
-                x:self.cursor_position.x.saturating_sub(self.offset.x).saturating_add(self.row_num_indent), 
+            self.row_num_indent = self.document.get_row_num().to_string().len().saturating_add(1);
+            let mut screen = std::mem::take(&mut self.screen);
+            screen.clear();
+            self.draw_rows(&mut screen);
+            self.draw_finder(&mut screen);
+            self.draw_status_bar(&mut screen);
+            self.draw_message_bar(&mut screen);
+            self.terminal.draw(&mut screen)?;
+            self.screen = screen;
+            self.terminal.set_cursor_position(&Position {
+                x:self.cursor_column().saturating_add(self.row_num_indent), 
                 y:self.cursor_position.y.saturating_sub(self.offset.y),
             });
         }
         //Terminal::cursor_blink();
-        Terminal::cursor_show();
-        Terminal::flush()
+        self.terminal.cursor_show();
+        self.terminal.flush()
     }
 
-    fn draw_rows(&self) {
-        let height = self.terminal.size.height;
-        Terminal::set_colors(Colors::new(
-            TEXT_FOREGROUND_COLOR,
-            TEXT_BACKGROUND_COLOR
-        ));
+    // screen column of the cursor within the text area
+    fn cursor_column(&self) -> usize {
+        let Position {x, y} = self.cursor_position;
+        self.document
+            .get_row(y)
+            .map_or(0, |row| row.render(self.offset.x, x).width())
+    }
+
+    fn draw_rows(&self, screen: &mut Screen) {
+        let height = self.terminal.size.height as usize;
+        let text_colors = Colors::new(TEXT_FOREGROUND_COLOR, TEXT_BACKGROUND_COLOR);
+        let brackets = self.bracket_pair();
         for i in 0..height {
-            Terminal::clear_current_line();
-            let row_idx = self.offset.y + i as usize;
+            let row_idx = self.offset.y + i;
             if let Some(row) = self.document.get_row(row_idx) {
-                self.draw_row(row, row_idx.saturating_add(1));
+                self.draw_row(screen, i, &row, row_idx.saturating_add(1), &brackets);
             }
             else if self.document.is_empty() && i == height / 3 {
-                self.draw_home_page();
+                self.draw_home_page(screen, i);
             }
             else{
-                Terminal::set_colors(Colors::new(
-                        Color::Rgb { r: 250, g: 128, b: 114 }, 
-                        Color::Rgb { r: 240, g: 240, b: 240 }
-                ));
-                if i == 0 {
-                    let indent_fmt = " ".repeat(self.row_num_indent - 2).to_string();
-                    print!("{}{} ",indent_fmt, 1);
+                let line_number = if i == 0 {
+                    format!("{}1 ", " ".repeat(self.row_num_indent - 2))
                 }
-                else{
-                    let indent_fmt = " ".repeat(self.row_num_indent).to_string();
-                    print!("{}",indent_fmt);
-                }
-                Terminal::set_colors(Colors::new(
-                        TEXT_FOREGROUND_COLOR,
-                        TEXT_BACKGROUND_COLOR
-                ));
-                println!("\r");
+                else {
+                    " ".repeat(self.row_num_indent)
+                };
+                let x = screen.put_str(0, i, &line_number, LINE_NUMBER_COLORS);
+                screen.fill(x, i, text_colors);
             }
         }
     }
 
-    fn draw_row(&self, row: &Row, row_num: usize) {
+    fn draw_row(&self, screen: &mut Screen, line: usize, row: &Row, row_num: usize, brackets: &[Position]) {
         let start = self.offset.x;
         let end = start + self.terminal.size.width.saturating_sub(self.row_num_indent as u16) as usize;
-        let row = row.render(start, end);
         let indent_fmt = " ".repeat(self.row_num_indent - row_num.to_string().len() - 1).to_string();
-        Terminal::set_colors(Colors::new(
-            Color::Rgb { r: 250, g: 128, b: 114 }, 
-            Color::Rgb { r: 240, g: 240, b: 240 }
-        ));
-        print!("{}{} ",indent_fmt, row_num);
-        Terminal::set_colors(Colors::new(
-            TEXT_FOREGROUND_COLOR,
-            TEXT_BACKGROUND_COLOR
-        ));
-        println!("{}\r", row);
+        let mut column = screen.put_str(0, line, &format!("{}{} ", indent_fmt, row_num), LINE_NUMBER_COLORS);
+        let y = row_num.saturating_sub(1);
+        let selected = self.selected_columns(y, row);
+        let is_cursor = |x: usize| self.cursors.iter().any(|cursor| cursor.y == y && cursor.x == x);
+        let background = |x: usize| {
+            if is_cursor(x) {
+                CURSOR_BACKGROUND_COLOR
+            }
+            else if brackets.iter().any(|bracket| bracket.y == y && bracket.x == x) {
+                BRACKET_BACKGROUND_COLOR
+            }
+            else if selected.map_or(false, |(from, to)| x >= from && x < to) {
+                SELECTION_BACKGROUND_COLOR
+            }
+            else {
+                TEXT_BACKGROUND_COLOR
+            }
+        };
+        // an extra cursor past the end of the row is drawn on a blank cell
+        let cursor_after = start <= row.len() && row.len() < end && is_cursor(row.len());
+        let end = cmp::min(end, row.len());
+        let mut run_start = start;
+        while run_start < end {
+            let color = background(run_start);
+            let mut run_end = run_start.saturating_add(1);
+            while run_end < end && background(run_end) == color {
+                run_end += 1;
+            }
+            let colors = Colors::new(TEXT_FOREGROUND_COLOR, color);
+            column = screen.put_str(column, line, &row.render(run_start, run_end), colors);
+            run_start = run_end;
+        }
+        if cursor_after {
+            column = screen.put_str(column, line, " ", Colors::new(TEXT_FOREGROUND_COLOR, CURSOR_BACKGROUND_COLOR));
+        }
+        screen.fill(column, line, Colors::new(TEXT_FOREGROUND_COLOR, TEXT_BACKGROUND_COLOR));
+    }
+
+    // the ranked files on the left half of the text area, and the start of the
+    // highlighted one on the right
+    fn draw_finder(&self, screen: &mut Screen) {
+        let finder = match &self.finder {
+            Some(finder) => finder,
+            None => return,
+        };
+        let height = self.terminal.size.height as usize;
+        let split = self.terminal.size.width as usize / 2;
+        let text_colors = Colors::new(TEXT_FOREGROUND_COLOR, TEXT_BACKGROUND_COLOR);
+        let first = finder.selected().saturating_sub(height.saturating_sub(1));
+        let mut matches = finder.matches().skip(first);
+        for line in 0..height {
+            let mut x = 0;
+            let background = if first + line == finder.selected() {
+                SELECTION_BACKGROUND_COLOR
+            }
+            else {
+                TEXT_BACKGROUND_COLOR
+            };
+            if let Some((file, indices)) = matches.next() {
+                for (idx, c) in file.chars().enumerate() {
+                    let foreground = if indices.contains(&idx) {
+                        FUZZY_MATCH_COLOR
+                    }
+                    else {
+                        TEXT_FOREGROUND_COLOR
+                    };
+                    x = screen.put_str(x, line, &c.to_string(), Colors::new(foreground, background));
+                }
+            }
+            screen.fill(x, line, Colors::new(TEXT_FOREGROUND_COLOR, background));
+            // the preview is drawn over any file name too long for its half
+            let preview = finder.preview().get(line).map_or("", String::as_str);
+            let x = screen.put_str(split, line, &format!("\u{2502} {}", preview), text_colors);
+            screen.fill(x, line, text_colors);
+        }
+    }
+
+    // the selected grapheme range of row `y`, if the selection touches it
+    fn selected_columns(&self, y: usize, row: &Row) -> Option<(usize, usize)> {
+        if let Some((top, bottom, left, right)) = self.block() {
+            return if y >= top && y <= bottom {
+                Some((row.index_at_column(left), row.index_at_column(right)))
+            }
+            else {
+                None
+            };
+        }
+        let (start, end) = self.selection()?;
+        if y < start.y || y > end.y {
+            return None;
+        }
+        let from = if y == start.y { start.x } else { 0 };
+        let to = if y == end.y { end.x } else { row.len() };
+        Some((from, to))
     }
     
-    fn draw_home_page(&self) {
+    fn draw_home_page(&self, screen: &mut Screen, line: usize) {
         let mut welcome_message = format!("Hecto Editor -- version {}", VERSION);
         let width = self.terminal.size.width as usize;
         let len = welcome_message.len();
@@ -171,11 +449,12 @@ impl Editor {
         let padding = width.saturating_sub(len) / 2;
         let spaces = " ".repeat(padding.saturating_sub(1));
         welcome_message = format!("~{}{}", spaces, welcome_message);
-        welcome_message.truncate(width);
-        println!("{}\r", welcome_message);
+        let text_colors = Colors::new(TEXT_FOREGROUND_COLOR, TEXT_BACKGROUND_COLOR);
+        let x = screen.put_str(0, line, &welcome_message, text_colors);
+        screen.fill(x, line, text_colors);
     }
 
-    fn draw_status_bar(&self) {
+    fn draw_status_bar(&self, screen: &mut Screen) {
         let mut status: String;
         let terminal_width = self.terminal.size.width as usize;
         let modified_indicator = if self.document.is_dirty() {
@@ -186,45 +465,141 @@ impl Editor {
         };
         let mut file_name = "[No Name]".to_string();
         if let Some(name) = &self.document.file_name {
-            file_name = name.clone();
-            file_name.truncate(30);
+            file_name = name.chars().take(30).collect();
+        }
+        match &self.listing {
+            Some(Listing::Grep { pattern, .. }) => {
+                file_name = format!("[grep: {}]", pattern.chars().take(30).collect::<String>());
+            },
+            Some(Listing::Directory { path, .. }) => {
+                file_name = format!("[dir: {}]", display_dir(path).chars().take(30).collect::<String>());
+            },
+            None => (),
+        }
+        if !self.buffers.is_empty() {
+            file_name = format!("[{}/{}] {}", self.buffer_index + 1, self.buffers.len() + 1, file_name);
+        }
+        if self.document.is_read_only() {
+            file_name.push_str(" [RO]");
+        }
+        if self.recording.is_some() {
+            file_name.push_str(" [REC]");
         }
         status = format!("{} - {} lines {}", file_name, self.document.get_row_num(), modified_indicator);
+        let encoding = self
+            .document
+            .encoding()
+            .filter(|encoding| *encoding != UTF_8)
+            .map_or(String::new(), |encoding| format!("{} | ", encoding.name()));
         let cursor_indicator = format!(
-            "row {}, col {}",
+            "{} | {}row {}, col {}",
+            self.document.file_type().name(),
+            encoding,
             self.cursor_position.y.saturating_add(1),
             self.cursor_position.x.saturating_add(1),
         );
-        let len = status.len() + cursor_indicator.len();
+        let len = status.width() + cursor_indicator.width();
         status.push_str(&" ".repeat(terminal_width.saturating_sub(len)));
         status = format!("{}{}", status, cursor_indicator);
-        status.truncate(terminal_width);
-        Terminal::set_colors(Colors::new(BAR_FOREGROUND_COLOR,
-                                         BAR_BACKGROUND_COLOR));
-        println!("{}\r", status);
+        let line = self.terminal.size.height as usize;
+        screen.put_str(0, line, &status, BAR_COLORS);
     }
 
-    fn draw_message_bar(&self) {
-        Terminal::clear_current_line();
-        let mut text: String;
-        if Instant::now() - self.status_message.time < Duration::new(5, 0) {
+    fn draw_message_bar(&self, screen: &mut Screen) {
+        let text: String;
+        let recent = Instant::now() - self.status_message.time < Duration::new(5, 0);
+        // progress of work in the background shows over messages from before it started
+        let progress = self.progress().filter(|(started, _)| !recent || self.status_message.time < *started);
+        if let Some((_, progress)) = progress {
+            text = progress;
+        }
+        else if recent {
             text = self.status_message.message.clone();
-            text.truncate(self.terminal.size.width as usize);
         }
         else {
             text = String::from("HELP: Ctrl-Q = quit | Ctrl-S = save"); 
         }
-        print!("{}", text);
-        Terminal::reset_colors();
+        let line = self.terminal.size.height as usize + 1;
+        let x = screen.put_str(0, line, &text, BAR_COLORS);
+        screen.fill(x, line, BAR_COLORS);
+    }
+
+    // the work running in the background, when it started, and how far it got
+    fn progress(&self) -> Option<(Instant, String)> {
+        if let Some((grep, pattern)) = &self.grepping {
+            let text = format!("Searching {} for {}: {} files, Esc cancels", grep.root.display(), pattern, grep.files());
+            return Some((grep.started(), text));
+        }
+        let started = self.document.indexing_since()?;
+        Some((started, format!("Indexing lines... {}% (read-only)", self.document.index_progress())))
     }
 
     fn process_keypress(&mut self) -> Result<(), std::io::Error> {
-        let key_event = Terminal::read()?;
+        // a macro being played is not held up by waiting for the terminal
+        if self.playback.is_empty() {
+            if self.indexing {
+                self.finish_indexing();
+                if !self.terminal.poll(BACKGROUND_POLL_INTERVAL)? {
+                    self.finish_grep();
+                    return Ok(());
+                }
+            }
+            else {
+                let mut wait = self.autosave_wait().map_or(self.disk_check_wait(), |autosave| {
+                    cmp::min(autosave, self.disk_check_wait())
+                });
+                if self.grepping.is_some() {
+                    wait = cmp::min(wait, BACKGROUND_POLL_INTERVAL);
+                }
+                if wait.is_zero() || !self.terminal.poll(wait)? {
+                    if self.autosave_wait() == Some(Duration::ZERO) {
+                        self.write_swap();
+                    }
+                    if self.disk_check_wait().is_zero() {
+                        self.check_disk();
+                    }
+                    self.finish_grep();
+                    return Ok(());
+                }
+            }
+        }
+        let key_event = self.read_event()?;
         if let Event::Key(key_pressed) = key_event {
+            self.check_disk();
+            if (key_pressed.modifiers, key_pressed.code) == (KeyModifiers::NONE, KeyCode::Esc) {
+                if let Some((grep, _)) = self.grepping.take() {
+                    grep.cancel();
+                    self.status_message = StatusMessage::from("Grep cancelled".to_string());
+                    return Ok(());
+                }
+            }
+            let vertical = matches!(
+                (key_pressed.modifiers, key_pressed.code),
+                (KeyModifiers::NONE | KeyModifiers::SHIFT, KeyCode::Up | KeyCode::Down | KeyCode::PageUp | KeyCode::PageDown)
+            );
+            if !vertical {
+                self.desired_x = None;
+            }
+            if self.listing_key(&key_pressed)? {
+                self.scroll();
+                return Ok(());
+            }
+            if self.block_key(&key_pressed) || self.multi_cursor_key(&key_pressed) {
+                self.scroll();
+                return Ok(());
+            }
             match (key_pressed.modifiers, key_pressed.code) {
                 (KeyModifiers::CONTROL, KeyCode::Char('q')) => {
-                    if self.document.is_dirty() {
-                        let entered_sure = self.prompt("Quit without saving? Y/N ").unwrap_or(None);
+                    let dirty = usize::from(self.document.is_dirty())
+                        + self.buffers.iter().filter(|buffer| buffer.document.is_dirty()).count();
+                    if dirty > 0 {
+                        let question = if dirty > 1 {
+                            format!("{} buffers have unsaved changes. Quit without saving? Y/N ", dirty)
+                        }
+                        else {
+                            String::from("Quit without saving? Y/N ")
+                        };
+                        let entered_sure = self.prompt(&question).unwrap_or(None);
                         if let Some(sure) = entered_sure {
                             if sure.starts_with("Y") || sure.starts_with("y") {
                                 self.if_quit = true;
@@ -235,32 +610,236 @@ impl Editor {
                         self.if_quit = true;
                     }
                 },
+                (KeyModifiers::ALT, KeyCode::Char('n')) => {
+                    self.switch_buffer(true);
+                },
+                (KeyModifiers::ALT, KeyCode::Char('p')) => {
+                    self.switch_buffer(false);
+                },
+                (KeyModifiers::ALT, KeyCode::Char('r')) => {
+                    self.toggle_read_only();
+                },
+                (KeyModifiers::CONTROL, KeyCode::Char('r')) => {
+                    self.reload()?;
+                },
+                (KeyModifiers::CONTROL, KeyCode::Char('z')) => {
+                    self.suspend()?;
+                },
                 (KeyModifiers::CONTROL, KeyCode::Char('s')) => {
                     self.save();
                 },
-                (_, KeyCode::Enter) => {
-                    self.document.insert(&self.cursor_position, '\n');
-                    self.move_cursor(KeyCode::Right);
+                (KeyModifiers::CONTROL, KeyCode::Char('f')) => {
+                    if let Some(search) = self.search_prompt("Search", true)? {
+                        self.find(&search, true);
+                    }
+                },
+                (KeyModifiers::NONE, KeyCode::F(3)) | (KeyModifiers::SHIFT, KeyCode::F(3)) => {
+                    self.find_again(key_pressed.modifiers == KeyModifiers::NONE);
                 },
-                (_, KeyCode::Tab) => {
-                    for _ in 0..4 {
-                        self.document.insert(&self.cursor_position, ' ');
-                        self.move_cursor(KeyCode::Right);
+                (KeyModifiers::CONTROL, KeyCode::Char('t')) => {
+                    if self.begin_edit() {
+                        self.replace()?;
                     }
                 },
-                (_, KeyCode::Char(c)) => {
-                    self.document.insert(&self.cursor_position, c);
-                    self.move_cursor(KeyCode::Right);
+                (KeyModifiers::CONTROL, KeyCode::Char('g')) => {
+                    self.grep()?;
                 },
-                (_, KeyCode::Delete) => {
-                    self.document.delete(&self.cursor_position);
+                (KeyModifiers::CONTROL, KeyCode::Char('o')) => {
+                    self.find_file()?;
+                },
+                (KeyModifiers::CONTROL, KeyCode::Char('d')) => self.add_next_occurrence(),
+                (modifiers, KeyCode::Up) | (modifiers, KeyCode::Down)
+                    if modifiers == KeyModifiers::ALT | KeyModifiers::SHIFT =>
+                {
+                    self.add_cursor(key_pressed.code == KeyCode::Up);
+                },
+                (KeyModifiers::ALT, KeyCode::Char('c')) => self.add_column_cursors(),
+                (KeyModifiers::ALT, KeyCode::Char('m')) => self.toggle_recording(),
+                (KeyModifiers::ALT, KeyCode::Char('l')) => self.play_macro()?,
+                (KeyModifiers::ALT, KeyCode::Char('k')) => self.save_macro()?,
+                (KeyModifiers::ALT, KeyCode::Char('o')) => self.load_macro()?,
+                (KeyModifiers::ALT, KeyCode::Char('b')) => {
+                    self.block = !self.block;
+                    if self.block && self.mark.is_none() {
+                        self.mark = Some(self.cursor_position.clone());
+                    }
+                    self.status_message = StatusMessage::from(format!(
+                        "Block selection {}",
+                        if self.block { "on" } else { "off" }
+                    ));
+                },
+                (KeyModifiers::CONTROL, KeyCode::Char('c')) => self.copy(false),
+                (KeyModifiers::CONTROL, KeyCode::Char('x')) => {
+                    if self.begin_edit() {
+                        self.copy(true);
+                    }
+                },
+                (KeyModifiers::CONTROL, KeyCode::Char('v')) => {
+                    if self.begin_edit() {
+                        self.paste();
+                    }
+                },
+                (KeyModifiers::ALT, KeyCode::Char('a')) => {
+                    if self.mark.is_some() {
+                        self.mark = None;
+                        self.status_message = StatusMessage::from("Mark Unset".to_string());
+                    }
+                    else {
+                        self.mark = Some(self.cursor_position.clone());
+                        self.status_message = StatusMessage::from("Mark Set".to_string());
+                    }
+                },
+                (KeyModifiers::ALT, KeyCode::Char('u')) => {
+                    if self.begin_edit() {
+                        let position = self.document.undo();
+                        self.restore_cursor(position, "Nothing to undo");
+                    }
+                },
+                (KeyModifiers::ALT, KeyCode::Char('e')) => {
+                    if self.begin_edit() {
+                        let position = self.document.redo();
+                        self.restore_cursor(position, "Nothing to redo");
+                    }
+                },
+                (KeyModifiers::ALT, KeyCode::Char('}')) => {
+                    if self.begin_edit() {
+                        self.indent_selection();
+                    }
+                },
+                (KeyModifiers::ALT, KeyCode::Char('{')) => {
+                    if self.begin_edit() {
+                        self.outdent_selection();
+                    }
+                },
+                (KeyModifiers::ALT, KeyCode::Char('3')) => {
+                    if self.begin_edit() {
+                        let at = self.cursor_position.clone();
+                        let has_token = self.edit_selected_rows(|document, start, end| {
+                            document.toggle_comment(start, end, &at)
+                        });
+                        if !has_token {
+                            self.status_message = StatusMessage::from(
+                                "No comment token for this file type".to_string()
+                            );
+                        }
+                    }
+                },
+                (KeyModifiers::ALT, KeyCode::Char(']')) => {
+                    if let Some(position) = self.document.matching_bracket(&self.cursor_position) {
+                        self.cursor_position = position;
+                    }
+                    else {
+                        self.status_message = StatusMessage::from("Not a bracket".to_string());
+                    }
+                },
+                (KeyModifiers::CONTROL, KeyCode::Char('k')) => {
+                    if self.begin_edit() {
+                        let (start, end) = self.selected_rows();
+                        let at = self.cursor_position.clone();
+                        self.document.delete_rows(start, end, &at);
+                        self.mark = None;
+                        self.restore_cursor(Some(Position {x: at.x, y: start}), "");
+                    }
+                },
+                (KeyModifiers::ALT, KeyCode::Char('d')) => {
+                    if self.begin_edit() {
+                        let (start, end) = self.selected_rows();
+                        let at = self.cursor_position.clone();
+                        self.document.duplicate_rows(start, end, &at);
+                        self.shift_rows_down(end - start);
+                    }
+                },
+                (KeyModifiers::CONTROL, KeyCode::Char('j')) => {
+                    if self.begin_edit() {
+                        let (start, end) = self.selected_rows();
+                        let at = self.cursor_position.clone();
+                        if let Some(x) = self.document.join_rows(start, end, &at) {
+                            self.mark = None;
+                            self.cursor_position = Position {x, y: start};
+                        }
+                    }
+                },
+                (KeyModifiers::ALT, KeyCode::Up) | (KeyModifiers::ALT, KeyCode::Down) => {
+                    if self.begin_edit() {
+                        let (start, end) = self.selected_rows();
+                        let at = self.cursor_position.clone();
+                        let up = key_pressed.code == KeyCode::Up;
+                        if self.document.move_rows(start, end, up, &at) {
+                            if up {
+                                self.cursor_position.y -= 1;
+                                if let Some(mark) = self.mark.as_mut() {
+                                    mark.y = mark.y.saturating_sub(1);
+                                }
+                            }
+                            else {
+                                self.shift_rows_down(1);
+                            }
+                        }
+                    }
+                },
+                // most terminals send Ctrl-Backspace as 0x08, which reads as Ctrl-H
+                (KeyModifiers::CONTROL, KeyCode::Backspace)
+                | (KeyModifiers::CONTROL, KeyCode::Char('h'))
+                | (KeyModifiers::ALT, KeyCode::Backspace) => {
+                    if self.begin_edit() {
+                        self.mark = None;
+                        self.delete_word(false);
+                    }
+                },
+                (KeyModifiers::CONTROL, KeyCode::Delete) => {
+                    if self.begin_edit() {
+                        self.mark = None;
+                        self.delete_word(true);
+                    }
+                },
+                (_, KeyCode::Esc) => {
+                    self.mark = None;
+                    self.block = false;
+                    self.cursors.clear();
+                },
+                (_, KeyCode::Tab) if self.mark.is_some() => {
+                    if self.begin_edit() {
+                        self.indent_selection();
+                    }
+                },
+                (_, KeyCode::BackTab) => {
+                    if self.begin_edit() {
+                        self.outdent_selection();
+                    }
                 },
-                (_, KeyCode::Backspace) => {
-                    if self.cursor_position.x > 0 || self.cursor_position.y > 0 {
-                        self.move_cursor(KeyCode::Left);
-                        self.document.delete(&self.cursor_position);
+                (_, KeyCode::Enter)
+                | (_, KeyCode::Tab)
+                | (_, KeyCode::Char(_))
+                | (_, KeyCode::Delete)
+                | (_, KeyCode::Backspace) => {
+                    if self.begin_edit() {
+                        self.type_key(key_pressed.code);
                     }
                 },
+                (KeyModifiers::CONTROL, KeyCode::Home) => {
+                    self.cursor_position = Position::default();
+                },
+                (KeyModifiers::CONTROL, KeyCode::End) => {
+                    let y = self.document.get_row_num().saturating_sub(1);
+                    self.cursor_position = Position {x: self.row_len(y), y};
+                },
+                (KeyModifiers::CONTROL, KeyCode::Left) => self.move_word(false),
+                (KeyModifiers::CONTROL, KeyCode::Right) => self.move_word(true),
+                (KeyModifiers::CONTROL, KeyCode::Up) => self.move_paragraph(false),
+                (KeyModifiers::CONTROL, KeyCode::Down) => self.move_paragraph(true),
+                (KeyModifiers::SHIFT, KeyCode::Up)
+                | (KeyModifiers::SHIFT, KeyCode::Down)
+                | (KeyModifiers::SHIFT, KeyCode::Left)
+                | (KeyModifiers::SHIFT, KeyCode::Right)
+                | (KeyModifiers::SHIFT, KeyCode::PageUp)
+                | (KeyModifiers::SHIFT, KeyCode::PageDown)
+                | (KeyModifiers::SHIFT, KeyCode::End)
+                | (KeyModifiers::SHIFT, KeyCode::Home) => {
+                    if self.mark.is_none() {
+                        self.mark = Some(self.cursor_position.clone());
+                    }
+                    self.move_cursor(key_pressed.code);
+                },
                 (_, KeyCode::Up)
                 | (_, KeyCode::Down)
                 | (_, KeyCode::Left)
@@ -276,6 +855,158 @@ impl Editor {
         Ok(())
     }
 
+    fn suspend(&mut self) -> Result<(), std::io::Error> {
+        self.terminal.suspend()?;
+        self.redraw_all();
+        Ok(())
+    }
+
+    // after the terminal was handed to someone else; a fresh screen has no
+    // previous frame, so the next one is drawn in full
+    fn redraw_all(&mut self) {
+        self.screen.resize(
+            self.terminal.size.width as usize,
+            self.terminal.size.height as usize + 2,
+        );
+        self.scroll();
+    }
+
+    // swaps the active document, and where the user was in it, with `buffer`
+    fn exchange(&mut self, buffer: &mut Buffer) {
+        std::mem::swap(&mut self.document, &mut buffer.document);
+        std::mem::swap(&mut self.cursor_position, &mut buffer.cursor_position);
+        std::mem::swap(&mut self.offset, &mut buffer.offset);
+        std::mem::swap(&mut self.mark, &mut buffer.mark);
+        std::mem::swap(&mut self.block, &mut buffer.block);
+        std::mem::swap(&mut self.indexing, &mut buffer.indexing);
+        std::mem::swap(&mut self.swap, &mut buffer.swap);
+        std::mem::swap(&mut self.swap_revision, &mut buffer.swap_revision);
+        std::mem::swap(&mut self.disk_warned, &mut buffer.disk_warned);
+        std::mem::swap(&mut self.listing, &mut buffer.listing);
+        std::mem::swap(&mut self.cursors, &mut buffer.cursors);
+        self.desired_x = None;
+    }
+
+    // makes `document` the active one, right after the one active until now
+    fn open_buffer(&mut self, document: Document, listing: Option<Listing>) {
+        let mut buffer = Buffer {
+            indexing: document.is_indexing(),
+            document,
+            listing,
+            ..Buffer::default()
+        };
+        self.exchange(&mut buffer);
+        self.buffers.insert(self.buffer_index, buffer);
+        self.buffer_index += 1;
+    }
+
+    // switches to the buffer holding the file at `path`, opening it first if
+    // there is none; a file opened here is checked for a swap file as the
+    // ones given at startup are
+    fn open_file(&mut self, path: &Path) -> Result<bool, std::io::Error> {
+        let wanted = fs::canonicalize(path).ok();
+        let is_wanted = |document: &Document| {
+            wanted.is_some()
+                && document.file_name.as_ref().and_then(|name| fs::canonicalize(name).ok()) == wanted
+        };
+        if is_wanted(&self.document) {
+            return Ok(true);
+        }
+        if self.buffers.iter().any(|buffer| is_wanted(&buffer.document)) {
+            while !is_wanted(&self.document) {
+                self.switch_buffer(true);
+            }
+            return Ok(true);
+        }
+        let name = path.to_string_lossy();
+        match open_document(&name, &self.config, None) {
+            Ok(document) => {
+                self.open_buffer(document, None);
+                self.check_swap()?;
+                Ok(true)
+            },
+            Err(err) => {
+                self.status_message = StatusMessage::from(format!("ERR: Could not open {}: {}", name, err));
+                Ok(false)
+            },
+        }
+    }
+
+    // adds `document` after the others without switching to it
+    pub fn add_buffer(&mut self, document: Document) {
+        self.push_buffer(document, None);
+    }
+
+    // replaces the active buffer with a listing of `dir`
+    pub fn open_directory(&mut self, dir: &Path) {
+        self.show_directory(dir, None);
+    }
+
+    fn push_buffer(&mut self, document: Document, listing: Option<Listing>) {
+        self.buffers.push(Buffer {
+            indexing: document.is_indexing(),
+            document,
+            listing,
+            ..Buffer::default()
+        });
+    }
+
+    fn switch_buffer(&mut self, forward: bool) {
+        if self.buffers.is_empty() {
+            self.status_message = StatusMessage::from("No other buffers".to_string());
+            return;
+        }
+        // the active document sits between buffers[..buffer_index] and the rest
+        if forward && self.buffer_index < self.buffers.len() {
+            let mut buffer = std::mem::take(&mut self.buffers[self.buffer_index]);
+            self.exchange(&mut buffer);
+            self.buffers[self.buffer_index] = buffer;
+            self.buffer_index += 1;
+        }
+        else if forward {
+            let mut buffer = self.buffers.remove(0);
+            self.exchange(&mut buffer);
+            self.buffers.push(buffer);
+            self.buffer_index = 0;
+        }
+        else if self.buffer_index > 0 {
+            self.buffer_index -= 1;
+            let mut buffer = std::mem::take(&mut self.buffers[self.buffer_index]);
+            self.exchange(&mut buffer);
+            self.buffers[self.buffer_index] = buffer;
+        }
+        else if let Some(mut buffer) = self.buffers.pop() {
+            self.exchange(&mut buffer);
+            self.buffers.insert(0, buffer);
+            self.buffer_index = self.buffers.len();
+        }
+        let name = self.document.file_name.clone().unwrap_or_else(|| "[No Name]".to_string());
+        self.status_message = StatusMessage::from(name);
+    }
+
+    // runs `f` with each open document active in turn, the current one first
+    fn each_buffer<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&mut Self),
+    {
+        f(self);
+        for idx in 0..self.buffers.len() {
+            let mut buffer = std::mem::take(&mut self.buffers[idx]);
+            self.exchange(&mut buffer);
+            f(self);
+            self.exchange(&mut buffer);
+            self.buffers[idx] = buffer;
+        }
+    }
+
+    // progress is drawn by the message bar; only the end of indexing is reported
+    fn finish_indexing(&mut self) {
+        if !self.document.is_indexing() {
+            self.indexing = false;
+            self.status_message = StatusMessage::from(format!("Indexed {} lines (read-only)", self.document.get_row_num()));
+        }
+    }
+
     fn scroll(&mut self) {
         let Position {x, y} = self.cursor_position;
         let terminal_height = self.terminal.size.height as usize;
@@ -306,6 +1037,9 @@ impl Editor {
         else {
             0
         };
+        if matches!(key, KeyCode::Up | KeyCode::Down | KeyCode::PageUp | KeyCode::PageDown) {
+            x = *self.desired_x.get_or_insert(x);
+        }
         match key {
             KeyCode::Up => {
                 y = y.saturating_sub(1);
@@ -368,61 +1102,1755 @@ impl Editor {
         if x > document_width {
             x = document_width;
         }
+        let forward = matches!(key, KeyCode::Down | KeyCode::Right | KeyCode::PageDown);
+        if self.playing && forward && (x, y) == (self.cursor_position.x, self.cursor_position.y) {
+            self.macro_stop = Some("the end of the file was reached");
+        }
         self.cursor_position = Position {x, y};
     }
 
-    fn save(&mut self) {
-        if self.document.file_name.is_none() {
-            let new_name = self.prompt("Save as: ").unwrap_or(None);
-            if new_name.is_none() {
-                self.status_message = StatusMessage::from("No file name".to_string());
-                return;
-            }
-            self.document.file_name = new_name;
+    // the text typed by a key at the cursor
+    fn type_key(&mut self, code: KeyCode) {
+        self.mark = None;
+        match code {
+            KeyCode::Enter => {
+                // the line break and the indent after it are undone together
+                self.document.begin_group();
+                let indent = self.document.new_line_indent(&self.cursor_position, self.config.tab_width);
+                self.document.insert(&self.cursor_position, '\n');
+                self.move_cursor(KeyCode::Right);
+                for c in indent.chars() {
+                    self.document.insert(&self.cursor_position, c);
+                    self.move_cursor(KeyCode::Right);
+                }
+                self.document.end_group();
+            },
+            KeyCode::Tab => {
+                let unit = self.document.file_type().indent_rules().unit(self.config.tab_width);
+                for c in unit.chars() {
+                    self.document.insert(&self.cursor_position, c);
+                    self.move_cursor(KeyCode::Right);
+                }
+            },
+            KeyCode::Char(c) => {
+                let blank_row = self.document.get_row(self.cursor_position.y)
+                    .map_or(false, |row| row.indentation().len() == row.len());
+                if blank_row && self.document.file_type().indent_rules().is_closer(c) {
+                    self.unindent();
+                }
+                self.document.insert(&self.cursor_position, c);
+                self.move_cursor(KeyCode::Right);
+            },
+            KeyCode::Delete => {
+                self.document.delete(&self.cursor_position);
+            },
+            KeyCode::Backspace => {
+                if self.document.unindent_width(&self.cursor_position, self.config.tab_width) > 0 {
+                    self.unindent();
+                }
+                else if self.cursor_position.x > 0 || self.cursor_position.y > 0 {
+                    self.move_cursor(KeyCode::Left);
+                    self.document.delete(&self.cursor_position);
+                }
+            },
+            _ => (),
         }
-        if self.document.save().is_ok() {
-            self.status_message = StatusMessage::from("File saved successfully".to_string());
+    }
+
+    fn unindent(&mut self) {
+        for _ in 0..self.document.unindent_width(&self.cursor_position, self.config.tab_width) {
+            self.move_cursor(KeyCode::Left);
+            self.document.delete(&self.cursor_position);
+        }
+    }
+
+    // the selection as (start, end) in document order
+    fn selection(&self) -> Option<(Position, Position)> {
+        let mark = self.mark.clone()?;
+        let cursor = self.cursor_position.clone();
+        if (mark.y, mark.x) <= (cursor.y, cursor.x) {
+            Some((mark, cursor))
         }
         else {
-            self.status_message = StatusMessage::from("Error occurred while saving".to_string());
+            Some((cursor, mark))
         }
     }
 
-    fn prompt(&mut self, prompt: &str) -> Result<Option<String>, std::io::Error> {
-        let mut ret = String::new();
-        loop {
-            self.status_message = StatusMessage::from(format!("{}{}", prompt, ret));
-            self.refresh_screen()?;
-            let key_event = Terminal::read().unwrap();
-            if let Event::Key(key) = key_event {
-                match key.code {
-                    KeyCode::Backspace => {
-                        ret.truncate(ret.len().saturating_sub(1));
-                    },
-                    KeyCode::Enter => break,
-                    KeyCode::Char(c) => {
-                        if !c.is_control() {
-                            ret.push(c);
-                        }
-                    }
-                    KeyCode::Esc => {
-                        ret.truncate(0);
-                        break;
-                    }
-                    _ => (),
+    // rows [start, end) covered by the selection, or the current row
+    fn selected_rows(&self) -> (usize, usize) {
+        match self.selection() {
+            Some((start, end)) => {
+                if end.x == 0 && end.y > start.y {
+                    (start.y, end.y)
                 }
-            }
+                else {
+                    (start.y, end.y.saturating_add(1))
+                }
+            },
+            None => (self.cursor_position.y, self.cursor_position.y.saturating_add(1)),
         }
-        if ret.is_empty() {
-            Ok(None)
+    }
+
+    fn row_len(&self, y: usize) -> usize {
+        self.document.get_row(y).map_or(0, |row| row.len())
+    }
+
+    // applies a row-level edit to the selected rows, keeping the cursor and
+    // mark on the same text
+    fn edit_selected_rows<F>(&mut self, edit: F) -> bool
+    where
+        F: FnOnce(&mut Document, usize, usize) -> bool,
+    {
+        let (start, end) = self.selected_rows();
+        let cursor_len = self.row_len(self.cursor_position.y);
+        let mark_len = self.mark.as_ref().map_or(0, |mark| self.row_len(mark.y));
+        let edited = edit(&mut self.document, start, end);
+        let Position {x, y} = self.cursor_position;
+        self.cursor_position.x = cmp::min(
+            x.saturating_add(self.row_len(y)).saturating_sub(cursor_len),
+            self.row_len(y),
+        );
+        if let Some(Position {x, y}) = self.mark.clone() {
+            self.mark = Some(Position {
+                x: cmp::min(x.saturating_add(self.row_len(y)).saturating_sub(mark_len), self.row_len(y)),
+                y,
+            });
         }
-        else{
-            Ok(Some(ret))
+        edited
+    }
+
+    fn shift_rows_down(&mut self, rows: usize) {
+        self.cursor_position.y = self.cursor_position.y.saturating_add(rows);
+        if let Some(mark) = self.mark.as_mut() {
+            mark.y = mark.y.saturating_add(rows);
         }
     }
+
+    fn indent_selection(&mut self) {
+        let width = self.config.tab_width;
+        let at = self.cursor_position.clone();
+        self.edit_selected_rows(|document, start, end| {
+            document.indent_rows(start, end, width, &at);
+            true
+        });
+    }
+
+    fn outdent_selection(&mut self) {
+        let width = self.config.tab_width;
+        let at = self.cursor_position.clone();
+        self.edit_selected_rows(|document, start, end| {
+            document.outdent_rows(start, end, width, &at);
+            true
+        });
+    }
+
+    fn restore_cursor(&mut self, position: Option<Position>, empty_message: &str) {
+        self.mark = None;
+        if let Some(Position {x, y}) = position {
+            let y = cmp::min(y, self.document.get_row_num().saturating_sub(1));
+            self.cursor_position = Position {
+                x: cmp::min(x, self.row_len(y)),
+                y,
+            };
+        }
+        else {
+            self.status_message = StatusMessage::from(empty_message.to_string());
+        }
+    }
+
+    // the cursor and its matching bracket, if the cursor sits on a bracket
+    fn bracket_pair(&self) -> Vec<Position> {
+        match self.document.matching_bracket(&self.cursor_position) {
+            Some(position) => vec![self.cursor_position.clone(), position],
+            None => Vec::new(),
+        }
+    }
+
+    fn move_word(&mut self, forward: bool) {
+        let Position {x, y} = self.cursor_position;
+        let target = self.document.get_row(y).map(|row| {
+            if forward {
+                row.next_word_end(x)
+            }
+            else {
+                row.prev_word_start(x)
+            }
+        });
+        match target {
+            Some(target) if target != x => self.cursor_position.x = target,
+            _ => self.move_cursor(if forward { KeyCode::Right } else { KeyCode::Left }),
+        }
+    }
+
+    fn delete_word(&mut self, forward: bool) {
+        let Position {x, y} = self.cursor_position;
+        let at = self.cursor_position.clone();
+        let target = self.document.get_row(y).map_or(x, |row| {
+            if forward {
+                row.next_word_end(x)
+            }
+            else {
+                row.prev_word_start(x)
+            }
+        });
+        if target > x {
+            self.document.delete_in_row(y, x, target, &at);
+        }
+        else if target < x {
+            self.document.delete_in_row(y, target, x, &at);
+            self.cursor_position.x = target;
+        }
+        else if forward {
+            self.document.delete(&at);
+        }
+        else if x > 0 || y > 0 {
+            self.move_cursor(KeyCode::Left);
+            self.document.delete(&self.cursor_position);
+        }
+    }
+
+    // moves to the previous/next blank row, skipping the blank rows next to the cursor
+    fn move_paragraph(&mut self, forward: bool) {
+        let is_blank = |y: usize| self.document.get_row(y).map_or(true, |row| row.is_blank());
+        let last = self.document.get_row_num().saturating_sub(1);
+        let mut y = self.cursor_position.y;
+        if forward {
+            while y < last && is_blank(y.saturating_add(1)) {
+                y += 1;
+            }
+            while y < last && !is_blank(y.saturating_add(1)) {
+                y += 1;
+            }
+            y = cmp::min(y.saturating_add(1), last);
+        }
+        else {
+            while y > 0 && is_blank(y - 1) {
+                y -= 1;
+            }
+            while y > 0 && !is_blank(y - 1) {
+                y -= 1;
+            }
+            y = y.saturating_sub(1);
+        }
+        self.cursor_position = Position {x: 0, y};
+    }
+
+    // rows [top, bottom] and screen columns [left, right) of the block
+    // selection, so that it stays a rectangle over wide characters
+    fn block(&self) -> Option<(usize, usize, usize, usize)> {
+        if !self.block {
+            return None;
+        }
+        let mark = self.mark.as_ref()?;
+        let (mark_column, column) = (self.column_of(mark), self.column_of(&self.cursor_position));
+        let y = self.cursor_position.y;
+        Some((
+            cmp::min(mark.y, y),
+            cmp::max(mark.y, y),
+            cmp::min(mark_column, column),
+            cmp::max(mark_column, column),
+        ))
+    }
+
+    fn column_of(&self, at: &Position) -> usize {
+        self.document.get_row(at.y).map_or(0, |row| row.column(at.x))
+    }
+
+    // the position at screen column `column` of row `y`, or its end
+    fn at_column(&self, y: usize, column: usize) -> Position {
+        Position {
+            x: self.document.get_row(y).map_or(0, |row| row.index_at_column(column)),
+            y,
+        }
+    }
+
+    // replaces rows [top, bottom] with `rows` unless that changes nothing
+    fn set_rows(&mut self, top: usize, bottom: usize, rows: Vec<String>) {
+        let unchanged = rows.len() == bottom.saturating_add(1).saturating_sub(top)
+            && rows.iter().enumerate().all(|(idx, text)| {
+                self.document.get_row(top + idx).map_or(false, |row| row.as_str() == text)
+            });
+        if !unchanged {
+            let at = self.cursor_position.clone();
+            self.document.replace_rows(top, bottom.saturating_add(1), rows, &at);
+        }
+    }
+
+    // typing with a block selected types on every row of it: the block is
+    // emptied, rows ending before its left edge are padded with spaces, and
+    // each row gets a cursor there. Backspace and Delete only empty it. The
+    // padding and the typing are undone together
+    fn block_key(&mut self, key: &KeyEvent) -> bool {
+        let (top, bottom, left, right) = match self.block() {
+            Some(block) => block,
+            None => return false,
+        };
+        let deletes = match (key.modifiers, key.code) {
+            (KeyModifiers::NONE | KeyModifiers::SHIFT, KeyCode::Char(_)) | (KeyModifiers::NONE, KeyCode::Tab) => false,
+            (KeyModifiers::NONE, KeyCode::Backspace | KeyCode::Delete) => right > left,
+            _ => return false,
+        };
+        if self.refuse_read_only() {
+            return true;
+        }
+        let rows = (top..=bottom)
+            .map(|y| splice_columns(&self.document.get_row(y).unwrap_or_default(), left, right, "", !deletes))
+            .collect();
+        self.document.begin_group();
+        self.set_rows(top, bottom, rows);
+        self.block = false;
+        self.mark = None;
+        let y = self.cursor_position.y;
+        self.cursor_position = self.at_column(y, left);
+        self.cursors = (top..=bottom)
+            .filter(|row| *row != y)
+            .map(|row| self.at_column(row, left))
+            .collect();
+        if !deletes && !self.multi_cursor_key(key) {
+            self.type_key(key.code);
+        }
+        self.document.end_group();
+        true
+    }
+
+    // puts the selection, or the block, on the clipboard, taking it out of
+    // the document when cutting
+    fn copy(&mut self, cut: bool) {
+        let (rows, block) = if let Some((top, bottom, left, right)) = self.block() {
+            let mut rows = Vec::new();
+            let mut remaining = Vec::new();
+            for y in top..=bottom {
+                let row = self.document.get_row(y).unwrap_or_default();
+                let text = row.slice(row.index_at_column(left), row.index_at_column(right));
+                // short rows are padded so the block pastes as a rectangle
+                let width = Row::from(text.as_str()).width();
+                rows.push(format!("{}{}", text, " ".repeat((right - left).saturating_sub(width))));
+                remaining.push(splice_columns(&row, left, right, "", false));
+            }
+            if cut {
+                self.set_rows(top, bottom, remaining);
+                self.cursor_position = self.at_column(self.cursor_position.y, left);
+            }
+            (rows, true)
+        }
+        else if let Some((start, end)) = self.selection() {
+            let first = self.document.get_row(start.y).unwrap_or_default();
+            let last = self.document.get_row(end.y).unwrap_or_default();
+            let rows: Vec<String> = (start.y..=end.y)
+                .map(|y| {
+                    let row = self.document.get_row(y).unwrap_or_default();
+                    let from = if y == start.y { start.x } else { 0 };
+                    let to = if y == end.y { end.x } else { row.len() };
+                    row.slice(from, to)
+                })
+                .collect();
+            if cut {
+                let joined = format!("{}{}", first.slice(0, start.x), last.slice(end.x, last.len()));
+                self.set_rows(start.y, end.y, vec![joined]);
+                self.cursor_position = start;
+            }
+            (rows, false)
+        }
+        else {
+            self.status_message = StatusMessage::from("Nothing selected".to_string());
+            return;
+        };
+        self.status_message = StatusMessage::from(format!(
+            "{} {} row(s){}",
+            if cut { "Cut" } else { "Copied" },
+            rows.len(),
+            if block { " of a block" } else { "" }
+        ));
+        self.clipboard = Some(Clipboard {rows, block});
+        self.mark = None;
+        self.block = false;
+    }
+
+    // inserts the clipboard at the cursor; a block goes into the same column
+    // of the rows from the cursor down, padding rows and adding them as needed
+    fn paste(&mut self) {
+        let clipboard = match &self.clipboard {
+            Some(clipboard) => clipboard.clone(),
+            None => {
+                self.status_message = StatusMessage::from("Nothing to paste".to_string());
+                return;
+            },
+        };
+        self.mark = None;
+        self.block = false;
+        let Position {x, y} = self.cursor_position;
+        let count = clipboard.rows.len();
+        if clipboard.block {
+            let column = self.column_of(&self.cursor_position);
+            let rows = clipboard.rows
+                .iter()
+                .enumerate()
+                .map(|(idx, text)| {
+                    let row = self.document.get_row(y + idx).unwrap_or_default();
+                    splice_columns(&row, column, column, text, true)
+                })
+                .collect();
+            self.set_rows(y, y + count - 1, rows);
+            let width = clipboard.rows.first().map_or(0, |text| Row::from(text.as_str()).width());
+            self.cursor_position = self.at_column(y, column + width);
+        }
+        else {
+            let row = self.document.get_row(y).unwrap_or_default();
+            let mut rows = clipboard.rows;
+            let last = rows.len() - 1;
+            rows[0].insert_str(0, &row.slice(0, x));
+            let end_x = Row::from(rows[last].as_str()).len();
+            rows[last].push_str(&row.slice(x, row.len()));
+            self.set_rows(y, y, rows);
+            self.cursor_position = Position {x: end_x, y: y + last};
+        }
+    }
+
+    // types or moves at every cursor when there are extra ones; false for
+    // keys that only the main cursor follows
+    fn multi_cursor_key(&mut self, key: &KeyEvent) -> bool {
+        if self.cursors.is_empty() {
+            return false;
+        }
+        let edits = match (key.modifiers, key.code) {
+            (KeyModifiers::NONE | KeyModifiers::SHIFT, KeyCode::Char(_))
+            | (KeyModifiers::NONE, KeyCode::Enter | KeyCode::Tab | KeyCode::Backspace | KeyCode::Delete) => true,
+            (
+                KeyModifiers::NONE,
+                KeyCode::Up | KeyCode::Down | KeyCode::Left | KeyCode::Right | KeyCode::Home | KeyCode::End,
+            ) => false,
+            _ => return false,
+        };
+        if edits && self.refuse_read_only() {
+            return true;
+        }
+        self.mark = None;
+        // going from the end of the document back, an edit never moves the
+        // text under the cursors still to come; the ones already done are
+        // shifted past it
+        let mut cursors: Vec<(Position, bool)> = self.cursors.drain(..).map(|at| (at, false)).collect();
+        cursors.push((self.cursor_position.clone(), true));
+        cursors.sort_by_key(|(at, _)| Reverse((at.y, at.x)));
+        if edits {
+            self.document.begin_group();
+        }
+        let mut done: Vec<(Position, bool)> = Vec::with_capacity(cursors.len());
+        for (at, main) in cursors {
+            self.cursor_position = at.clone();
+            if edits {
+                // Delete changes the text up to the next character
+                let old = if key.code == KeyCode::Delete {
+                    self.move_cursor(KeyCode::Right);
+                    std::mem::replace(&mut self.cursor_position, at.clone())
+                }
+                else {
+                    at
+                };
+                self.type_key(key.code);
+                for (other, _) in &mut done {
+                    *other = shift_position(other, &old, &self.cursor_position);
+                }
+            }
+            else {
+                self.desired_x = None;
+                self.move_cursor(key.code);
+            }
+            done.push((self.cursor_position.clone(), main));
+        }
+        if edits {
+            self.document.end_group();
+        }
+        self.desired_x = None;
+        for (at, main) in done {
+            if main {
+                self.cursor_position = at;
+            }
+            else {
+                self.cursors.push(at);
+            }
+        }
+        self.merge_cursors();
+        true
+    }
+
+    // drops the extra cursors that ended up on the main one or on each other
+    fn merge_cursors(&mut self) {
+        let mut seen = vec![(self.cursor_position.y, self.cursor_position.x)];
+        self.cursors.retain(|cursor| {
+            let at = (cursor.y, cursor.x);
+            if seen.contains(&at) {
+                false
+            }
+            else {
+                seen.push(at);
+                true
+            }
+        });
+    }
+
+    fn show_cursor_count(&mut self) {
+        self.status_message = StatusMessage::from(format!("{} cursors, Esc to keep one", self.cursors.len() + 1));
+    }
+
+    // moves the main cursor to the row above or below, leaving an extra
+    // cursor where it was
+    fn add_cursor(&mut self, up: bool) {
+        let at = self.cursor_position.clone();
+        self.mark = None;
+        self.move_cursor(if up { KeyCode::Up } else { KeyCode::Down });
+        if self.cursor_position.y == at.y {
+            return;
+        }
+        self.cursors.push(at);
+        self.merge_cursors();
+        self.show_cursor_count();
+    }
+
+    // selects the word under the cursor, or once a word is selected, selects
+    // its next occurrence and leaves an extra cursor at the end of the last one
+    fn add_next_occurrence(&mut self) {
+        let (start, end) = match self.selection() {
+            Some(selection) => selection,
+            None => {
+                let Position {x, y} = self.cursor_position;
+                match self.document.get_row(y).and_then(|row| row.word_at(x)) {
+                    Some((from, to)) => {
+                        self.mark = Some(Position {x: from, y});
+                        self.cursor_position = Position {x: to, y};
+                    },
+                    None => {
+                        self.status_message = StatusMessage::from("No word under the cursor".to_string());
+                    },
+                }
+                return;
+            },
+        };
+        if start.y != end.y || start.x == end.x {
+            self.status_message = StatusMessage::from("Select some text within a row first".to_string());
+            return;
+        }
+        let text = self.document.get_row(start.y).map_or(String::new(), |row| row.slice(start.x, end.x));
+        let search = match SearchOptions::default().compile(&text) {
+            Ok(search) => search,
+            Err(_) => return,
+        };
+        let found = self.document.find(&search, &end, true, None);
+        let taken = |at: &Position| {
+            (at.y, at.x) == (end.y, end.x) || self.cursors.iter().any(|cursor| (cursor.y, cursor.x) == (at.y, at.x))
+        };
+        match found {
+            Some((found_start, found_end)) if !taken(&found_end) => {
+                self.cursors.push(end);
+                self.mark = Some(found_start);
+                self.cursor_position = found_end;
+                self.merge_cursors();
+                self.show_cursor_count();
+            },
+            _ => {
+                self.status_message = StatusMessage::from(format!("No more occurrences of {}", text));
+            },
+        }
+    }
+
+    // an extra cursor on every other selected row, in the column of the main one
+    fn add_column_cursors(&mut self) {
+        let (start, end) = match self.selection() {
+            Some((start, end)) if start.y != end.y => (start, end),
+            _ => {
+                self.status_message = StatusMessage::from("Select several rows first".to_string());
+                return;
+            },
+        };
+        let Position {x, y} = self.cursor_position;
+        self.mark = None;
+        for row in (start.y..=end.y).filter(|row| *row != y) {
+            self.cursors.push(Position {
+                x: cmp::min(x, self.row_len(row)),
+                y: row,
+            });
+        }
+        self.merge_cursors();
+        self.show_cursor_count();
+    }
+
+    fn toggle_recording(&mut self) {
+        if self.playing {
+            return;
+        }
+        let message = match self.recording.take() {
+            Some(mut keys) => {
+                // the last key read is the Alt-M that stops the recording
+                keys.pop();
+                let message = format!("Recorded {} keys, Alt-L to play them, Alt-K to save them", keys.len());
+                self.macro_keys = keys;
+                message
+            },
+            None => {
+                self.recording = Some(Vec::new());
+                String::from("Recording a macro, Alt-M to stop")
+            },
+        };
+        self.status_message = StatusMessage::from(message);
+    }
+
+    // plays the macro the given number of times, or until the end of the
+    // file when no number is given; a failing search or a move that hits
+    // the end of the file stops it early
+    fn play_macro(&mut self) -> Result<(), std::io::Error> {
+        if self.playing || self.recording.is_some() {
+            return Ok(());
+        }
+        if self.macro_keys.is_empty() {
+            self.status_message = StatusMessage::from("No macro recorded, Alt-M to record one".to_string());
+            return Ok(());
+        }
+        let label = |_: &Self| String::from("Play macro how many times (Enter for until the end of the file): ");
+        let times = match self.prompt_with(label, |_, _, _| false)? {
+            None => return Ok(()),
+            Some(times) if times.trim().is_empty() => None,
+            Some(times) => match times.trim().parse::<usize>() {
+                Ok(times) => Some(times),
+                Err(_) => {
+                    self.status_message = StatusMessage::from(format!("Not a number: {}", times));
+                    return Ok(());
+                },
+            },
+        };
+        self.playing = true;
+        self.macro_stop = None;
+        let result = self.repeat_macro(times);
+        self.playing = false;
+        self.playback.clear();
+        let runs = result?;
+        let stop = self.macro_stop.take().map_or(String::new(), |reason| format!(", stopped as {}", reason));
+        self.status_message = StatusMessage::from(format!("Played the macro {} time(s){}", runs, stop));
+        Ok(())
+    }
+
+    // plays the macro `times` times, or until it stops changing anything,
+    // and returns how many runs were played
+    fn repeat_macro(&mut self, times: Option<usize>) -> Result<usize, std::io::Error> {
+        let mut runs = 0;
+        while runs < times.unwrap_or(MAX_MACRO_RUNS) && self.macro_stop.is_none() && !self.if_quit {
+            let before = (self.cursor_position.x, self.cursor_position.y, self.document.revision());
+            self.playback.extend(self.macro_keys.iter().copied());
+            while !self.playback.is_empty() && !self.if_quit && self.macro_stop.is_none() {
+                self.process_keypress()?;
+            }
+            runs += 1;
+            // a run that changed nothing would repeat forever
+            let after = (self.cursor_position.x, self.cursor_position.y, self.document.revision());
+            if times.is_none() && before == after {
+                break;
+            }
+            if let Some(signal) = self.signals.as_ref().and_then(Signals::received) {
+                self.terminate(signal);
+            }
+            if self.playback_interrupted()? {
+                self.macro_stop = Some("it was interrupted");
+            }
+        }
+        Ok(runs)
+    }
+
+    // Esc or Ctrl-C stops a macro between runs; other keys pressed while it
+    // plays are dropped
+    fn playback_interrupted(&mut self) -> Result<bool, std::io::Error> {
+        while self.terminal.poll(Duration::ZERO)? {
+            if let Event::Key(key) = self.terminal.read()? {
+                if matches!(
+                    (key.modifiers, key.code),
+                    (KeyModifiers::NONE, KeyCode::Esc) | (KeyModifiers::CONTROL, KeyCode::Char('c'))
+                ) {
+                    return Ok(true);
+                }
+            }
+        }
+        Ok(false)
+    }
+
+    fn save_macro(&mut self) -> Result<(), std::io::Error> {
+        if self.playing {
+            return Ok(());
+        }
+        if self.macro_keys.is_empty() {
+            self.status_message = StatusMessage::from("No macro recorded, Alt-M to record one".to_string());
+            return Ok(());
+        }
+        let name = match self.prompt("Save macro as: ")? {
+            Some(name) => name,
+            None => return Ok(()),
+        };
+        self.status_message = StatusMessage::from(match macros::save(&name, &self.macro_keys) {
+            Ok(path) => format!("Saved macro {} to {}", name, path.display()),
+            Err(err) => format!("ERR: Could not save macro {}: {}", name, err),
+        });
+        Ok(())
+    }
+
+    fn load_macro(&mut self) -> Result<(), std::io::Error> {
+        if self.playing {
+            return Ok(());
+        }
+        let name = match self.prompt("Load macro: ")? {
+            Some(name) => name,
+            None => return Ok(()),
+        };
+        self.status_message = StatusMessage::from(match macros::load(&name) {
+            Ok(keys) => {
+                let message = format!("Loaded macro {} ({} keys), Alt-L to play it", name, keys.len());
+                self.macro_keys = keys;
+                message
+            },
+            Err(err) => format!("ERR: Could not load macro {}: {}", name, err),
+        });
+        Ok(())
+    }
+
+    fn save(&mut self) {
+        if self.document.file_name.is_none() {
+            let new_name = self.prompt("Save as: ").unwrap_or(None);
+            if new_name.is_none() {
+                self.status_message = StatusMessage::from("No file name".to_string());
+                return;
+            }
+            self.document.file_name = new_name;
+        }
+        if self.document.is_read_only() {
+            self.save_elsewhere("Buffer is read-only");
+            return;
+        }
+        if self.document.is_changed_on_disk()
+            && !self.confirm("File changed on disk since it was read. Overwrite? Y/N ").unwrap_or(false)
+        {
+            self.status_message = StatusMessage::from("Save cancelled".to_string());
+            return;
+        }
+        match self.document.save() {
+            Ok(()) => self.saved(),
+            Err(err) if err.kind() == io::ErrorKind::PermissionDenied => {
+                self.save_elsewhere("Permission denied");
+            },
+            Err(_) => {
+                self.status_message = StatusMessage::from("Error occurred while saving".to_string());
+            },
+        }
+    }
+
+    fn saved(&mut self) {
+        self.remove_swap();
+        self.disk_warned = false;
+        self.status_message = StatusMessage::from("File saved successfully".to_string());
+    }
+
+    // for files that cannot be written as they are: write them through the
+    // sudo command, or to some other path
+    fn save_elsewhere(&mut self, reason: &str) {
+        let question = format!(
+            "{}. S = save with {}, A = save as: ",
+            reason, self.config.sudo_command
+        );
+        let answer = self.prompt(&question).unwrap_or(None).unwrap_or_default().to_lowercase();
+        match answer.chars().next() {
+            Some('s') => {
+                if let Err(err) = self.sudo_save() {
+                    self.status_message = StatusMessage::from(format!("ERR: {}", err));
+                }
+            },
+            Some('a') => {
+                let file_name = if let Some(file_name) = self.prompt("Save as: ").unwrap_or(None) {
+                    file_name
+                }
+                else {
+                    self.status_message = StatusMessage::from("No file name".to_string());
+                    return;
+                };
+                if self.document.save_as(&file_name).is_ok() {
+                    self.saved();
+                }
+                else {
+                    self.status_message = StatusMessage::from(format!("ERR: Could not write {}", file_name));
+                }
+            },
+            _ => self.status_message = StatusMessage::from("Save cancelled".to_string()),
+        }
+    }
+
+    // pipes the document into `sudo tee FILE`; the terminal is handed back
+    // meanwhile so that sudo can ask for a password
+    fn sudo_save(&mut self) -> Result<(), std::io::Error> {
+        let file_name = self.document.file_name.clone().unwrap_or_default();
+        let bytes = self.document.to_bytes();
+        self.terminal.release()?;
+        let status = process::Command::new(&self.config.sudo_command)
+            .arg("tee")
+            .arg(&file_name)
+            .stdin(process::Stdio::piped())
+            .stdout(process::Stdio::null())
+            .spawn()
+            .and_then(|mut child| {
+                // stdin is closed once written, and the child is waited for
+                // even when it stopped reading early
+                let written = child.stdin.take().map_or(Ok(()), |mut stdin| stdin.write_all(&bytes));
+                let status = child.wait();
+                written.and(status)
+            });
+        self.terminal.reclaim()?;
+        self.redraw_all();
+        if !status?.success() {
+            return Err(io::Error::new(io::ErrorKind::Other, format!("{} tee failed", self.config.sudo_command)));
+        }
+        self.document.mark_saved(&bytes);
+        self.saved();
+        self.status_message = StatusMessage::from(format!("Saved {} with {}", file_name, self.config.sudo_command));
+        Ok(())
+    }
+
+    fn toggle_read_only(&mut self) {
+        if self.listing.is_some() {
+            self.status_message = StatusMessage::from("Listings are always read-only".to_string());
+            return;
+        }
+        if !self.document.can_edit() {
+            self.status_message = StatusMessage::from("Large files are read-only".to_string());
+            return;
+        }
+        let read_only = !self.document.is_read_only();
+        self.document.set_read_only(read_only);
+        self.status_message = StatusMessage::from(if read_only {
+            "Buffer is now read-only".to_string()
+        }
+        else {
+            "Buffer can be edited".to_string()
+        });
+    }
+
+    // called by every key that changes the text before it does so: a
+    // read-only buffer refuses the edit, and other cursors are dropped since
+    // an edit made for the main cursor alone would leave them pointing at
+    // the wrong text
+    fn begin_edit(&mut self) -> bool {
+        if self.refuse_read_only() {
+            return false;
+        }
+        self.cursors.clear();
+        true
+    }
+
+    fn refuse_read_only(&mut self) -> bool {
+        if !self.document.is_read_only() {
+            return false;
+        }
+        self.status_message = StatusMessage::from(if !self.document.can_edit() {
+            "Large files are read-only".to_string()
+        }
+        else {
+            "Buffer is read-only, Alt-R to allow edits".to_string()
+        });
+        true
+    }
+
+    // asks for a pattern; Alt-R, Alt-I and Alt-W toggle regex, case-insensitive
+    // and whole-word matching, Alt-S limits a `scoped` search to the
+    // selection, and an empty pattern repeats the last search
+    fn search_prompt(&mut self, action: &str, scoped: bool) -> Result<Option<Search>, std::io::Error> {
+        let action = action.to_string();
+        let input = self.prompt_with(
+            |editor| {
+                let last = editor.last_search.as_ref().map_or(String::new(), |last| format!(" [{}]", last));
+                let mut options = editor.search_options;
+                options.in_selection &= scoped;
+                format!("{}{}{}: ", action, options.describe(), last)
+            },
+            |editor, key, _| {
+                if key.modifiers != KeyModifiers::ALT {
+                    return false;
+                }
+                let options = &mut editor.search_options;
+                match key.code {
+                    KeyCode::Char('r') => options.regex = !options.regex,
+                    KeyCode::Char('i') => options.ignore_case = !options.ignore_case,
+                    KeyCode::Char('w') => options.whole_word = !options.whole_word,
+                    KeyCode::Char('s') if scoped => options.in_selection = !options.in_selection,
+                    _ => return false,
+                }
+                true
+            },
+        )?;
+        let pattern = match input {
+            Some(input) if !input.is_empty() => input,
+            Some(_) if self.last_search.is_some() => self.last_search.clone().unwrap_or_default(),
+            _ => return Ok(None),
+        };
+        self.last_search = Some(pattern.clone());
+        if !scoped {
+            return Ok(self.compile_search(&pattern));
+        }
+        self.search_scope = None;
+        if self.search_options.in_selection {
+            if let Some(selection) = self.selection() {
+                self.search_scope = Some(selection);
+            }
+            else {
+                self.status_message = StatusMessage::from("No selection to search in".to_string());
+                return Ok(None);
+            }
+        }
+        Ok(self.compile_search(&pattern))
+    }
+
+    fn compile_search(&mut self, pattern: &str) -> Option<Search> {
+        match self.search_options.compile(pattern) {
+            Ok(search) => Some(search),
+            Err(reason) => {
+                self.status_message = StatusMessage::from(format!("Invalid pattern: {}", reason));
+                None
+            },
+        }
+    }
+
+    // selects the next match after the cursor, or the previous one before the
+    // selection
+    fn find(&mut self, search: &Search, forward: bool) {
+        if self.indexing {
+            self.status_message = StatusMessage::from("Search is available once indexing is done".to_string());
+            return;
+        }
+        if !self.document.is_searchable() {
+            self.status_message = StatusMessage::from(NOT_SEARCHABLE.to_string());
+            return;
+        }
+        let from = if forward {
+            self.cursor_position.clone()
+        }
+        else {
+            self.selection().map_or(self.cursor_position.clone(), |(start, _)| start)
+        };
+        if let Some((start, end)) = self.document.find(search, &from, forward, self.search_scope.as_ref()) {
+            self.mark = Some(start);
+            self.cursor_position = end;
+        }
+        else {
+            let pattern = self.last_search.clone().unwrap_or_default();
+            self.status_message = StatusMessage::from(format!("Not found: {}", pattern));
+            if self.playing {
+                self.macro_stop = Some("a search failed");
+            }
+        }
+    }
+
+    fn find_again(&mut self, forward: bool) {
+        let pattern = if let Some(pattern) = self.last_search.clone() {
+            pattern
+        }
+        else {
+            self.status_message = StatusMessage::from("No previous search".to_string());
+            return;
+        };
+        if let Some(search) = self.compile_search(&pattern) {
+            self.find(&search, forward);
+        }
+    }
+
+    // replaces the matches from the cursor to the end of the document, or
+    // those in the selection searched, asking about each one in turn
+    fn replace(&mut self) -> Result<(), std::io::Error> {
+        let search = match self.search_prompt("Replace", true)? {
+            Some(search) => search,
+            None => return Ok(()),
+        };
+        let replacement = match self.prompt_with(|_| "Replace with: ".to_string(), |_, _, _| false)? {
+            Some(replacement) => replacement,
+            None => return Ok(()),
+        };
+        // the text is flattened once for the whole session, not for every match
+        let mut snapshot = match self.document.snapshot() {
+            Some(snapshot) => snapshot,
+            None => {
+                self.status_message = StatusMessage::from(NOT_SEARCHABLE.to_string());
+                return Ok(());
+            },
+        };
+        let mut at = self
+            .search_scope
+            .as_ref()
+            .map_or(self.cursor_position.clone(), |(start, _)| start.clone());
+        let mut replaced = 0;
+        while let Some((start, end)) = self.document.find_in(&snapshot, &search, &at, self.search_scope.as_ref()) {
+            self.mark = Some(start.clone());
+            self.cursor_position = end.clone();
+            self.scroll();
+            match self.ask_key("Replace? Y = yes, N = no, A = all, Esc = stop")? {
+                Some('y') => {
+                    let to = self.search_scope.as_ref().map(|(_, to)| to.clone());
+                    let (count, replaced_end) =
+                        self.document.replace(&mut snapshot, &search, &replacement, &start, to.as_ref(), Some(1));
+                    // the selection searched ends after the match, so it moves with its end
+                    if let Some((_, to)) = self.search_scope.as_mut() {
+                        *to = shift_position(to, &end, &replaced_end);
+                    }
+                    replaced += count;
+                    at = replaced_end;
+                },
+                Some('n') => at = end,
+                Some('a') => {
+                    let to = self.search_scope.as_ref().map(|(_, to)| to.clone());
+                    let (count, end) =
+                        self.document.replace(&mut snapshot, &search, &replacement, &start, to.as_ref(), None);
+                    replaced += count;
+                    at = end;
+                    break;
+                },
+                _ => break,
+            }
+        }
+        self.mark = None;
+        self.cursor_position = at;
+        self.status_message = StatusMessage::from(format!(
+            "Replaced {} occurrence{}",
+            replaced,
+            if replaced == 1 { "" } else { "s" }
+        ));
+        Ok(())
+    }
+
+    // lets the user pick a file of the project by typing parts of its path,
+    // and opens it
+    fn find_file(&mut self) -> Result<(), std::io::Error> {
+        let root = grep::project_root(&self.location());
+        self.finder = Some(Finder::new(&root, finder::list_files(&root)));
+        let input = self.prompt_with(
+            |editor| {
+                let (count, total) = editor.finder.as_ref().map_or((0, 0), |finder| (finder.match_count(), finder.len()));
+                format!("Open [{}/{}]: ", count, total)
+            },
+            |editor, key, input| {
+                let height = editor.terminal.size.height as isize;
+                let finder = match editor.finder.as_mut() {
+                    Some(finder) => finder,
+                    None => return false,
+                };
+                match (key.modifiers, key.code) {
+                    (_, KeyCode::Up) => finder.move_selection(-1),
+                    (_, KeyCode::Down) => finder.move_selection(1),
+                    (_, KeyCode::PageUp) => finder.move_selection(-height),
+                    (_, KeyCode::PageDown) => finder.move_selection(height),
+                    (KeyModifiers::NONE | KeyModifiers::SHIFT, KeyCode::Char(c)) => {
+                        input.push(c);
+                        finder.filter(input);
+                    },
+                    (_, KeyCode::Backspace) => {
+                        input.pop();
+                        finder.filter(input);
+                    },
+                    _ => return false,
+                }
+                true
+            },
+        );
+        let finder = self.finder.take();
+        if input?.is_none() {
+            return Ok(());
+        }
+        match finder.and_then(|finder| finder.selected_path()) {
+            Some(path) => {
+                // shown unless opening the file has something to say
+                let path = relative_to_cwd(&path);
+                self.status_message = StatusMessage::from(path.to_string_lossy().into_owned());
+                self.open_file(&path)?;
+            },
+            None => self.status_message = StatusMessage::from("No matching files".to_string()),
+        }
+        Ok(())
+    }
+
+    // searches the project the current file is in and lists the matching
+    // lines in a new buffer
+    fn grep(&mut self) -> Result<(), std::io::Error> {
+        let search = match self.search_prompt("Grep", false)? {
+            Some(search) => search,
+            None => return Ok(()),
+        };
+        let pattern = self.last_search.clone().unwrap_or_default();
+        let root = grep::project_root(&self.location());
+        if let Some((running, _)) = self.grepping.take() {
+            running.cancel();
+        }
+        self.grepping = Some((Grep::spawn(root, search), pattern));
+        Ok(())
+    }
+
+    // opens the results of the grep running in the background once it is
+    // done
+    fn finish_grep(&mut self) {
+        let locations = match self.grepping.as_ref().and_then(|(grep, _)| grep.results()) {
+            Some(locations) => locations,
+            None => return,
+        };
+        let (grep, pattern) = match self.grepping.take() {
+            Some(grepping) => grepping,
+            None => return,
+        };
+        let root = grep.root;
+        if locations.is_empty() {
+            self.status_message = StatusMessage::from(format!("Not found: {}", pattern));
+            return;
+        }
+        let mut text = format!("{} matches for {} in {}\n", locations.len(), pattern, root.display());
+        let mut targets = vec![None];
+        for location in locations {
+            let path = relative_to_cwd(&location.path);
+            text.push_str(&format!("{}:{}: {}\n", path.display(), location.line + 1, location.text));
+            let x = Row::from(&location.text[..]).grapheme_index(location.column);
+            targets.push(Some((path, Position {x, y: location.line})));
+        }
+        let count = targets.len() - 1;
+        let mut document = Document::from_text(&text);
+        document.set_read_only(true);
+        self.open_buffer(document, Some(Listing::Grep { pattern, targets }));
+        self.cursor_position = Position {x: 0, y: cmp::min(1, count)};
+        self.status_message = StatusMessage::from(format!("{} matches, Enter opens one", count));
+    }
+
+    // the file, or the directory listed, in the active buffer; where
+    // project-wide commands start from
+    fn location(&self) -> PathBuf {
+        match (&self.listing, &self.document.file_name) {
+            (Some(Listing::Directory { path, .. }), _) => path.clone(),
+            (_, Some(file_name)) => PathBuf::from(file_name),
+            _ => PathBuf::from("."),
+        }
+    }
+
+    // keys with a meaning of their own in grep results and directory
+    // listings; false for any other key
+    fn listing_key(&mut self, key: &KeyEvent) -> Result<bool, std::io::Error> {
+        let dir = match &self.listing {
+            Some(Listing::Directory { path, .. }) => Some(path.clone()),
+            Some(Listing::Grep { .. }) => None,
+            None => return Ok(false),
+        };
+        match (key.modifiers, key.code, dir) {
+            (KeyModifiers::NONE, KeyCode::Enter, _) => self.follow_listing()?,
+            (KeyModifiers::NONE, KeyCode::Backspace, Some(dir)) => {
+                if let Some(parent) = dir.parent() {
+                    self.show_directory(parent, Some(&dir));
+                }
+            },
+            (KeyModifiers::NONE, KeyCode::Char('n'), Some(dir)) => self.create_entry(&dir)?,
+            (KeyModifiers::NONE, KeyCode::Char('r'), Some(dir)) => self.rename_entry(&dir)?,
+            (KeyModifiers::NONE, KeyCode::Char('d'), Some(dir)) => self.delete_entry(&dir)?,
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    // opens what the row under the cursor leads to; a directory is listed
+    // in place of the current one
+    fn follow_listing(&mut self) -> Result<(), std::io::Error> {
+        let y = self.cursor_position.y;
+        let (path, position) = match &self.listing {
+            Some(Listing::Grep { targets, .. }) => match targets.get(y).cloned().flatten() {
+                Some((path, position)) => (path, Some(position)),
+                None => return Ok(()),
+            },
+            Some(Listing::Directory { path: dir, targets }) => match targets.get(y).cloned().flatten() {
+                Some(path) if path.is_dir() => {
+                    let dir = dir.clone();
+                    self.show_directory(&path, Some(&dir));
+                    return Ok(());
+                },
+                Some(path) => (relative_to_cwd(&path), None),
+                None => return Ok(()),
+            },
+            None => return Ok(()),
+        };
+        if self.open_file(&path)? {
+            if let Some(position) = position {
+                let y = cmp::min(position.y, self.document.get_row_num().saturating_sub(1));
+                self.mark = None;
+                self.cursor_position = Position {
+                    x: cmp::min(position.x, self.row_len(y)),
+                    y,
+                };
+            }
+        }
+        Ok(())
+    }
+
+    // lists `dir` in the active buffer, with the cursor on the row of
+    // `select` if it is listed
+    fn show_directory(&mut self, dir: &Path, select: Option<&Path>) {
+        match directory_listing(dir) {
+            Ok((document, listing)) => {
+                let row = match (&listing, select) {
+                    (Listing::Directory { targets, .. }, Some(select)) => {
+                        targets.iter().position(|target| target.as_deref() == Some(select))
+                    },
+                    _ => None,
+                };
+                let y = row.unwrap_or_else(|| cmp::min(1, document.get_row_num().saturating_sub(1)));
+                self.document = document;
+                self.listing = Some(listing);
+                self.mark = None;
+                self.cursors.clear();
+                self.offset = Position::default();
+                self.cursor_position = Position {x: 0, y};
+            },
+            Err(err) => {
+                self.status_message = StatusMessage::from(format!("ERR: Could not list {}: {}", dir.display(), err));
+            },
+        }
+    }
+
+    // the file or directory on the cursor row of the listing of `dir`
+    fn listed_entry(&mut self, dir: &Path) -> Option<PathBuf> {
+        let entry = match &self.listing {
+            Some(Listing::Directory { targets, .. }) => targets.get(self.cursor_position.y).cloned().flatten(),
+            _ => None,
+        };
+        let entry = entry.filter(|entry| entry.parent() == Some(dir));
+        if entry.is_none() {
+            self.status_message = StatusMessage::from("No file or directory on this row".to_string());
+        }
+        entry
+    }
+
+    fn create_entry(&mut self, dir: &Path) -> Result<(), std::io::Error> {
+        let name = match self.prompt("New file (end with / for a directory): ")? {
+            Some(name) => name,
+            None => return Ok(()),
+        };
+        match browser::create(dir, &name) {
+            Ok(()) => {
+                let top = name.split('/').next().unwrap_or_default();
+                self.show_directory(dir, Some(&dir.join(top)));
+                self.status_message = StatusMessage::from(format!("Created {}", name));
+            },
+            Err(err) => {
+                self.status_message = StatusMessage::from(format!("ERR: Could not create {}: {}", name, err));
+            },
+        }
+        Ok(())
+    }
+
+    fn rename_entry(&mut self, dir: &Path) -> Result<(), std::io::Error> {
+        let path = match self.listed_entry(dir) {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let name = path.file_name().map_or(String::new(), |name| name.to_string_lossy().into_owned());
+        let new_name = match self.prompt(&format!("Rename {} to: ", name))? {
+            Some(new_name) => new_name,
+            None => return Ok(()),
+        };
+        let target = dir.join(&new_name);
+        let open = self.buffers_under(&path);
+        // rename would replace an existing file without a word
+        let renamed = if target.exists() {
+            Err(io::Error::new(io::ErrorKind::AlreadyExists, "already exists"))
+        }
+        else {
+            fs::rename(&path, &target)
+        };
+        match renamed {
+            Ok(()) => {
+                for (idx, rest) in &open {
+                    let moved = if rest.as_os_str().is_empty() { target.clone() } else { target.join(rest) };
+                    self.buffers[*idx].document.moved_to(&relative_to_cwd(&moved).to_string_lossy());
+                }
+                self.show_directory(dir, Some(&target));
+                self.status_message = StatusMessage::from(format!(
+                    "Renamed {} to {}{}",
+                    name,
+                    new_name,
+                    open_buffers(open.len(), "renamed along")
+                ));
+            },
+            Err(err) => {
+                self.status_message = StatusMessage::from(format!("ERR: Could not rename {}: {}", name, err));
+            },
+        }
+        Ok(())
+    }
+
+    fn delete_entry(&mut self, dir: &Path) -> Result<(), std::io::Error> {
+        let path = match self.listed_entry(dir) {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let name = path.file_name().map_or(String::new(), |name| name.to_string_lossy().into_owned());
+        if !self.confirm(&format!("Delete {}? Y/N ", name))? {
+            return Ok(());
+        }
+        let open = self.buffers_under(&path);
+        match browser::delete(&path) {
+            Ok(()) => {
+                for (idx, _) in &open {
+                    self.buffers[*idx].document.file_deleted();
+                }
+                let y = self.cursor_position.y;
+                self.show_directory(dir, None);
+                self.cursor_position.y = cmp::min(y, self.document.get_row_num().saturating_sub(1));
+                self.status_message = StatusMessage::from(format!(
+                    "Deleted {}{}",
+                    name,
+                    open_buffers(open.len(), "now unsaved")
+                ));
+            },
+            Err(err) => {
+                self.status_message = StatusMessage::from(format!("ERR: Could not delete {}: {}", name, err));
+            },
+        }
+        Ok(())
+    }
+
+    // the other buffers holding `path`, or a file under it, each with the
+    // rest of its path; files are compared as `open_file` does
+    fn buffers_under(&self, path: &Path) -> Vec<(usize, PathBuf)> {
+        let path = match fs::canonicalize(path) {
+            Ok(path) => path,
+            Err(_) => return Vec::new(),
+        };
+        self.buffers
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, buffer)| {
+                let file = fs::canonicalize(buffer.document.file_name.as_ref()?).ok()?;
+                file.strip_prefix(&path).ok().map(|rest| (idx, rest.to_path_buf()))
+            })
+            .collect()
+    }
+
+    fn disk_check_wait(&self) -> Duration {
+        DISK_CHECK_INTERVAL.saturating_sub(self.disk_check_time.elapsed())
+    }
+
+    // tells the user, once, when the file was changed by someone else
+    fn check_disk(&mut self) {
+        self.disk_check_time = Instant::now();
+        if self.disk_warned || !self.document.is_changed_on_disk() {
+            return;
+        }
+        self.disk_warned = true;
+        let file_name = self.document.file_name.clone().unwrap_or_default();
+        self.status_message = StatusMessage::from(if self.document.is_dirty() {
+            format!("WARN: {} changed on disk, Ctrl-R reloads it and drops your edits", file_name)
+        }
+        else {
+            format!("{} changed on disk, Ctrl-R to reload", file_name)
+        });
+    }
+
+    // reads the file again, keeping the cursor where it was as far as possible
+    fn reload(&mut self) -> Result<(), std::io::Error> {
+        if let Some(Listing::Directory { path, targets }) = &self.listing {
+            let path = path.clone();
+            let selected = targets.get(self.cursor_position.y).cloned().flatten();
+            self.show_directory(&path, selected.as_deref());
+            return Ok(());
+        }
+        let file_name = if let Some(file_name) = self.document.file_name.clone() {
+            file_name
+        }
+        else {
+            self.status_message = StatusMessage::from("No file to reload".to_string());
+            return Ok(());
+        };
+        if self.document.is_dirty() && !self.confirm("Drop unsaved changes and reload? Y/N ")? {
+            return Ok(());
+        }
+        match open_document(&file_name, &self.config, self.document.encoding()) {
+            Ok(document) => {
+                self.document = document;
+                self.indexing = self.document.is_indexing();
+                self.remove_swap();
+                self.disk_warned = false;
+                self.mark = None;
+                self.cursors.clear();
+                let y = cmp::min(self.cursor_position.y, self.document.get_row_num());
+                self.cursor_position = Position {
+                    x: cmp::min(self.cursor_position.x, self.row_len(y)),
+                    y,
+                };
+                self.scroll();
+                self.status_message = StatusMessage::from(format!("Reloaded {}", file_name));
+            },
+            Err(_) => {
+                self.status_message = StatusMessage::from(format!("ERR: Could not reload {}", file_name));
+            },
+        }
+        Ok(())
+    }
+
+    fn confirm(&mut self, question: &str) -> Result<bool, std::io::Error> {
+        let answer = self.prompt(question)?.unwrap_or_default();
+        Ok(answer.starts_with('y') || answer.starts_with('Y'))
+    }
+
+    // time left until the swap file is due, if the document has edits that
+    // are not in it yet
+    fn autosave_wait(&self) -> Option<Duration> {
+        if self.config.autosave_interval == 0
+            || !self.document.is_dirty()
+            || self.document.revision() == self.swap_revision
+        {
+            return None;
+        }
+        let interval = Duration::from_secs(self.config.autosave_interval);
+        Some(interval.saturating_sub(self.swap_time.elapsed()))
+    }
+
+    fn write_swap(&mut self) -> bool {
+        self.swap_time = Instant::now();
+        self.swap_revision = self.document.revision();
+        let mut written = false;
+        if let Some(swap) = Swap::for_file(self.document.file_name.as_deref()) {
+            written = swap.write(&self.document.contents(), &self.cursor_position).is_ok();
+            if !written {
+                self.status_message = StatusMessage::from(format!(
+                    "ERR: Could not write swap file {}",
+                    swap.path().display()
+                ));
+            }
+            // a save-as moves the swap file along with the document
+            if let Some(old) = self.swap.take() {
+                if old.path() != swap.path() {
+                    old.remove();
+                }
+            }
+            self.swap = Some(swap);
+        }
+        written
+    }
+
+    // copies unsaved edits to the swap file when the editor is going down;
+    // returns where they went
+    fn emergency_save(&mut self) -> Vec<PathBuf> {
+        let mut saved = Vec::new();
+        self.each_buffer(|editor| {
+            if editor.document.is_dirty() && editor.write_swap() {
+                saved.extend(editor.swap.as_ref().map(|swap| swap.path().to_path_buf()));
+            }
+        });
+        saved
+    }
+
+    // blocks until an event can be read, shutting down if a termination
+    // signal arrives in the meantime
+    fn read_event(&mut self) -> Result<Event, std::io::Error> {
+        if let Some(key) = self.playback.pop_front() {
+            return Ok(Event::Key(key));
+        }
+        if self.signals.is_some() {
+            loop {
+                if let Some(signal) = self.signals.as_ref().and_then(Signals::received) {
+                    self.terminate(signal);
+                }
+                if self.terminal.poll(SIGNAL_POLL_INTERVAL)? {
+                    break;
+                }
+            }
+        }
+        let event = self.terminal.read()?;
+        if let (Some(keys), Event::Key(key)) = (self.recording.as_mut(), &event) {
+            keys.push(*key);
+        }
+        Ok(event)
+    }
+
+    fn terminate(&mut self, signal: i32) -> ! {
+        let saved = self.emergency_save();
+        self.terminal.quit();
+        eprintln!("hecto: terminated by signal {}", signal);
+        for path in saved {
+            eprintln!("Unsaved changes were written to {}", path.display());
+        }
+        process::exit(128_i32.saturating_add(signal));
+    }
+
+    fn remove_swap(&mut self) {
+        if let Some(swap) = self.swap.take() {
+            swap.remove();
+        }
+        self.swap_revision = self.document.revision();
+    }
+
+    // a swap file left next to the file by an earlier session means its edits
+    // were never saved; offer to bring them back
+    fn check_swap(&mut self) -> Result<(), std::io::Error> {
+        let file_name = match &self.document.file_name {
+            Some(file_name) if !self.document.is_read_only() => file_name.clone(),
+            _ => return Ok(()),
+        };
+        let swap = match Swap::for_file(Some(&file_name)) {
+            Some(swap) if swap.exists() => swap,
+            _ => return Ok(()),
+        };
+        let recovery = if let Some(recovery) = swap.read() {
+            recovery
+        }
+        else {
+            self.status_message = StatusMessage::from(format!(
+                "ERR: Unreadable swap file {}",
+                swap.path().display()
+            ));
+            return Ok(());
+        };
+        // another editor still has the file open; recovering would take its
+        // edits over, and writing our own swap file would overwrite its one
+        if let Some(owner) = recovery.owner.as_ref().filter(|owner| owner.is_running()) {
+            self.document.set_read_only(true);
+            self.status_message = StatusMessage::from(format!(
+                "WARN: Also being edited by process {} on {}, opened read-only",
+                owner.pid, owner.host
+            ));
+            return Ok(());
+        }
+        let age = recovery
+            .modified
+            .and_then(|modified| modified.elapsed().ok())
+            .map_or(String::new(), |age| format!(" from {} ago", format_age(age)));
+        loop {
+            let question = format!("Swap file found{}. R = recover, D = diff, X = discard: ", age);
+            let answer = self.prompt(&question)?.unwrap_or_default().to_lowercase();
+            match answer.chars().next() {
+                Some('r') => {
+                    let rows = recovery.contents.lines().map(String::from).collect();
+                    let len = self.document.get_row_num();
+                    self.document.replace_rows(0, len, rows, &Position::default());
+                    let y = cmp::min(recovery.cursor.y, self.document.get_row_num());
+                    self.cursor_position = Position {
+                        x: cmp::min(recovery.cursor.x, self.row_len(y)),
+                        y,
+                    };
+                    self.scroll();
+                    // keep the swap file until the recovered text is saved
+                    self.swap = Some(swap);
+                    self.swap_revision = self.document.revision();
+                    self.status_message = StatusMessage::from("Recovered unsaved changes, Ctrl-S to keep them".to_string());
+                    return Ok(());
+                },
+                Some('d') => self.show_diff(&recovery.contents)?,
+                Some('x') => {
+                    swap.remove();
+                    self.status_message = StatusMessage::from("Swap file discarded".to_string());
+                    return Ok(());
+                },
+                _ => {
+                    self.status_message = StatusMessage::from(format!(
+                        "Swap file left at {}",
+                        swap.path().display()
+                    ));
+                    return Ok(());
+                },
+            }
+        }
+    }
+
+    // shows how `recovered` differs from the document until a key other than
+    // a scrolling key is pressed
+    fn show_diff(&mut self, recovered: &str) -> Result<(), std::io::Error> {
+        let current = self.document.contents();
+        let diff = TextDiff::from_lines(current.as_str(), recovered)
+            .unified_diff()
+            .header("on disk", "swap file")
+            .to_string();
+        let diff = if diff.is_empty() {
+            String::from("The swap file matches the file on disk")
+        }
+        else {
+            diff
+        };
+        let document = std::mem::replace(&mut self.document, Document::from_text(&diff));
+        let cursor = std::mem::take(&mut self.cursor_position);
+        let offset = std::mem::take(&mut self.offset);
+        self.status_message = StatusMessage::from("Swap file diff, any other key to go back".to_string());
+        loop {
+            self.refresh_screen()?;
+            if let Event::Key(key) = self.read_event()? {
+                match key.code {
+                    KeyCode::Up | KeyCode::Down | KeyCode::PageUp | KeyCode::PageDown => {
+                        self.move_cursor(key.code);
+                        self.scroll();
+                    },
+                    _ => break,
+                }
+            }
+        }
+        self.document = document;
+        self.cursor_position = cursor;
+        self.offset = offset;
+        Ok(())
+    }
+
+    fn prompt(&mut self, prompt: &str) -> Result<Option<String>, std::io::Error> {
+        let ret = self.prompt_with(|_| prompt.to_string(), |_, _, _| false)?;
+        Ok(ret.filter(|ret| !ret.is_empty()))
+    }
+
+    // a single key press answering `question`, lowercased; None for keys
+    // other than characters
+    fn ask_key(&mut self, question: &str) -> Result<Option<char>, std::io::Error> {
+        self.status_message = StatusMessage::from(question.to_string());
+        self.refresh_screen()?;
+        loop {
+            if let Event::Key(key) = self.read_event()? {
+                return Ok(match key.code {
+                    KeyCode::Char(c) => Some(c.to_ascii_lowercase()),
+                    _ => None,
+                });
+            }
+        }
+    }
+
+    // reads a line of input after `label`, which is redrawn with each key;
+    // `on_key` sees every key first and keeps those it returns true for from
+    // being typed. None when the prompt is cancelled with Esc
+    fn prompt_with<L, K>(&mut self, label: L, mut on_key: K) -> Result<Option<String>, std::io::Error>
+    where
+        L: Fn(&Self) -> String,
+        K: FnMut(&mut Self, &KeyEvent, &mut String) -> bool,
+    {
+        let mut ret = String::new();
+        loop {
+            self.status_message = StatusMessage::from(format!("{}{}", label(self), ret));
+            self.refresh_screen()?;
+            let key_event = self.read_event()?;
+            if let Event::Key(key) = key_event {
+                if on_key(self, &key, &mut ret) {
+                    continue;
+                }
+                match key.code {
+                    KeyCode::Backspace => {
+                        ret.truncate(ret.len().saturating_sub(1));
+                    },
+                    KeyCode::Enter => break,
+                    KeyCode::Char(c) => {
+                        if !c.is_control() {
+                            ret.push(c);
+                        }
+                    }
+                    KeyCode::Esc => return Ok(None),
+                    _ => (),
+                }
+            }
+        }
+        Ok(Some(ret))
+    }
+}
+
+// files over the large file threshold are opened read-only from a memory map;
+// a file that does not exist yet is created on the first save
+fn open_document(
+    file_name: &str,
+    config: &Config,
+    encoding: Option<&'static Encoding>,
+) -> Result<Document, std::io::Error> {
+    let metadata = match fs::metadata(file_name) {
+        Ok(metadata) => metadata,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Document::new_file(file_name)),
+        Err(err) => return Err(err),
+    };
+    if metadata.is_dir() {
+        return Err(std::io::Error::new(std::io::ErrorKind::Other, "is a directory"));
+    }
+    if metadata.len() >= config.large_file_threshold {
+        Document::open_large(file_name)
+    }
+    else {
+        Document::open_with(file_name, encoding)
+    }
+}
+
+// the rows of a directory listing: the directory itself, its parent, and its
+// entries with the directories first
+fn directory_listing(dir: &Path) -> Result<(Document, Listing), std::io::Error> {
+    let dir = fs::canonicalize(dir)?;
+    let entries = browser::read_dir(&dir)?;
+    let mut text = format!("{}/\n", display_dir(&dir));
+    let mut targets = vec![None];
+    if let Some(parent) = dir.parent() {
+        text.push_str("../\n");
+        targets.push(Some(parent.to_path_buf()));
+    }
+    for entry in entries {
+        text.push_str(&format!("{}{}\n", entry.name, if entry.is_dir { "/" } else { "" }));
+        targets.push(Some(dir.join(&entry.name)));
+    }
+    let mut document = Document::from_text(&text);
+    document.set_read_only(true);
+    Ok((document, Listing::Directory { path: dir, targets }))
+}
+
+fn display_dir(dir: &Path) -> String {
+    let shown = relative_to_cwd(dir);
+    if shown.as_os_str().is_empty() {
+        String::from(".")
+    }
+    else {
+        shown.display().to_string()
+    }
+}
+
+// tells what happened to the buffers open on a renamed or deleted file
+fn open_buffers(count: usize, what: &str) -> String {
+    match count {
+        0 => String::new(),
+        1 => format!(", 1 open buffer {}", what),
+        count => format!(", {} open buffers {}", count, what),
+    }
+}
+
+// paths are shown, and opened, relative to where the editor was started
+fn relative_to_cwd(path: &Path) -> PathBuf {
+    let cwd = env::current_dir().unwrap_or_default();
+    path.strip_prefix(&cwd).map_or_else(|_| path.to_path_buf(), Path::to_path_buf)
+}
+
+// `row` with screen columns [left, right) replaced by `text`; when `pad` is
+// set a row ending before `left` is first padded to it with spaces. A wide
+// character across `left` stays, one across `right` goes with the columns
+fn splice_columns(row: &Row, left: usize, right: usize, text: &str, pad: bool) -> String {
+    let padding = if pad { " ".repeat(left.saturating_sub(row.width())) } else { String::new() };
+    let (from, to) = (row.index_at_column(left), row.index_at_column(right));
+    format!("{}{}{}{}", row.slice(0, from), padding, text, row.slice(cmp::max(from, to), row.len()))
+}
+
+// where `at`, which lies after `old`, ends up once the text up to `old` is
+// changed so that it ends at `new`
+fn shift_position(at: &Position, old: &Position, new: &Position) -> Position {
+    if at.y == old.y {
+        Position {
+            x: at.x.saturating_add(new.x).saturating_sub(old.x),
+            y: new.y,
+        }
+    }
+    else {
+        Position {
+            x: at.x,
+            y: at.y.saturating_add(new.y).saturating_sub(old.y),
+        }
+    }
+}
+
+fn format_age(age: Duration) -> String {
+    let seconds = age.as_secs();
+    if seconds < 60 {
+        format!("{}s", seconds)
+    }
+    else if seconds < 60 * 60 {
+        format!("{}m", seconds / 60)
+    }
+    else if seconds < 24 * 60 * 60 {
+        format!("{}h", seconds / (60 * 60))
+    }
+    else {
+        format!("{}d", seconds / (24 * 60 * 60))
+    }
 }
 
+// `run` puts the terminal back before the error is printed
 fn err_panic(err: std::io::Error) {
-    Terminal::clear_screen();
     panic!("{}", err);
 }
\ No newline at end of file
@@ -0,0 +1,130 @@
+use std::ffi::OsStr;
+use std::path::Path;
+
+pub struct FileType {
+    name: String,
+    indent: IndentRules,
+    comment: Option<String>,
+}
+
+#[derive(Default)]
+pub struct IndentRules {
+    hard_tabs: bool,
+    openers: Vec<char>,
+    closers: Vec<char>,
+}
+
+impl Default for FileType {
+    fn default() -> Self {
+        Self {
+            name: String::from("No filetype"),
+            indent: IndentRules::default(),
+            comment: None,
+        }
+    }
+}
+
+impl FileType {
+    pub fn from(file_name: &str) -> Self {
+        let path = Path::new(file_name);
+        let name = path.file_name().and_then(OsStr::to_str).unwrap_or("");
+        let extension = path.extension().and_then(OsStr::to_str).unwrap_or("");
+        let brackets = IndentRules {
+            openers: vec!['{', '(', '['],
+            closers: vec!['}', ')', ']'],
+            ..IndentRules::default()
+        };
+        if name == "Makefile" || name == "makefile" {
+            return Self {
+                name: String::from("Makefile"),
+                indent: IndentRules {
+                    hard_tabs: true,
+                    openers: vec![':'],
+                    ..IndentRules::default()
+                },
+                comment: Some(String::from("#")),
+            };
+        }
+        match extension {
+            "rs" => Self {
+                name: String::from("Rust"),
+                indent: brackets,
+                comment: Some(String::from("//")),
+            },
+            "c" | "h" | "cpp" | "hpp" | "cc" | "java" | "go" | "js" | "ts" => Self {
+                name: String::from("C-like"),
+                indent: brackets,
+                comment: Some(String::from("//")),
+            },
+            "json" => Self {
+                name: String::from("JSON"),
+                indent: brackets,
+                comment: None,
+            },
+            "py" => Self {
+                name: String::from("Python"),
+                indent: IndentRules {
+                    openers: vec![':', '(', '[', '{'],
+                    closers: vec![')', ']', '}'],
+                    ..IndentRules::default()
+                },
+                comment: Some(String::from("#")),
+            },
+            _ => Self::default(),
+        }
+    }
+
+    pub fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    pub fn indent_rules(&self) -> &IndentRules {
+        &self.indent
+    }
+
+    pub fn comment(&self) -> Option<&str> {
+        self.comment.as_deref()
+    }
+}
+
+impl IndentRules {
+    pub fn unit(&self, width: usize) -> String {
+        if self.hard_tabs {
+            String::from("\t")
+        }
+        else {
+            " ".repeat(width)
+        }
+    }
+
+    pub fn is_opener(&self, c: char) -> bool {
+        self.openers.contains(&c)
+    }
+
+    pub fn is_closer(&self, c: char) -> bool {
+        self.closers.contains(&c)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_types_follow_the_file_name() {
+        assert_eq!(FileType::from("src/main.rs").name(), "Rust");
+        assert_eq!(FileType::from("/tmp/a.b/script.py").name(), "Python");
+        assert_eq!(FileType::from("lib/include/x.hpp").name(), "C-like");
+        assert_eq!(FileType::from("project/Makefile").name(), "Makefile");
+        assert!(FileType::from("makefile").indent_rules().hard_tabs);
+        assert_eq!(FileType::from("notes.txt").name(), "No filetype");
+    }
+
+    #[test]
+    fn names_without_an_extension_have_no_file_type() {
+        assert_eq!(FileType::from("rs").name(), "No filetype");
+        assert_eq!(FileType::from("src/json").name(), "No filetype");
+        assert_eq!(FileType::from(".py").name(), "No filetype");
+        assert_eq!(FileType::from("dir.rs/README").name(), "No filetype");
+    }
+}
@@ -0,0 +1,176 @@
+use std::cmp::Reverse;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use fuzzy_matcher::FuzzyMatcher;
+use fuzzy_matcher::skim::SkimMatcherV2;
+use ignore::WalkBuilder;
+
+const MAX_FILES: usize = 100_000;
+const PREVIEW_BYTES: u64 = 64 * 1024;
+
+// the files under a project root, ranked against what has been typed so far;
+// the highlighted one is read ahead for its preview
+pub struct Finder {
+    root: PathBuf,
+    files: Vec<String>, //relative to the root, in path order
+    matches: Vec<(usize, Vec<usize>)>, //index into `files` and the chars that matched
+    selected: usize,
+    preview: Vec<String>,
+}
+
+// the files under `root` that are not hidden or ignored by .gitignore and friends
+pub fn list_files(root: &Path) -> Vec<String> {
+    let mut files: Vec<String> = WalkBuilder::new(root)
+        .require_git(false)
+        .build()
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_type().map_or(false, |file_type| file_type.is_file()))
+        .filter_map(|entry| {
+            entry.path().strip_prefix(root).ok().map(|path| path.to_string_lossy().into_owned())
+        })
+        .take(MAX_FILES)
+        .collect();
+    files.sort();
+    files
+}
+
+impl Finder {
+    pub fn new(root: &Path, files: Vec<String>) -> Self {
+        let mut finder = Self {
+            root: root.to_path_buf(),
+            files,
+            matches: Vec::new(),
+            selected: 0,
+            preview: Vec::new(),
+        };
+        finder.filter("");
+        finder
+    }
+
+    // ranks the files against `query`: best fuzzy score first, then shorter
+    // paths; an empty query keeps them all in path order
+    pub fn filter(&mut self, query: &str) {
+        let matcher = SkimMatcherV2::default();
+        let mut ranked: Vec<(i64, usize, Vec<usize>)> = self
+            .files
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, file)| {
+                matcher.fuzzy_indices(file, query).map(|(score, indices)| (score, idx, indices))
+            })
+            .collect();
+        if !query.is_empty() {
+            let files = &self.files;
+            ranked.sort_by_key(|(score, idx, _)| (Reverse(*score), files[*idx].len(), *idx));
+        }
+        self.matches = ranked.into_iter().map(|(_, idx, indices)| (idx, indices)).collect();
+        self.selected = 0;
+        self.load_preview();
+    }
+
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    // the matching files in rank order, with the char positions that matched
+    pub fn matches(&self) -> impl Iterator<Item = (&str, &[usize])> {
+        self.matches.iter().map(|(idx, indices)| (self.files[*idx].as_str(), indices.as_slice()))
+    }
+
+    pub fn match_count(&self) -> usize {
+        self.matches.len()
+    }
+
+    pub fn selected(&self) -> usize {
+        self.selected
+    }
+
+    // moves the highlight by `rows`, staying within the matches
+    pub fn move_selection(&mut self, rows: isize) {
+        let last = self.matches.len().saturating_sub(1);
+        let selected = if rows < 0 {
+            self.selected.saturating_sub(rows.unsigned_abs())
+        }
+        else {
+            self.selected.saturating_add(rows.unsigned_abs())
+        };
+        let selected = selected.min(last);
+        if selected != self.selected {
+            self.selected = selected;
+            self.load_preview();
+        }
+    }
+
+    pub fn selected_path(&self) -> Option<PathBuf> {
+        let (idx, _) = self.matches.get(self.selected)?;
+        Some(self.root.join(&self.files[*idx]))
+    }
+
+    // the first lines of the highlighted file
+    pub fn preview(&self) -> &[String] {
+        &self.preview
+    }
+
+    fn load_preview(&mut self) {
+        self.preview = match self.selected_path() {
+            Some(path) => read_preview(&path),
+            None => Vec::new(),
+        };
+    }
+}
+
+fn read_preview(path: &Path) -> Vec<String> {
+    let mut bytes = Vec::new();
+    let read = File::open(path).and_then(|file| file.take(PREVIEW_BYTES).read_to_end(&mut bytes));
+    if read.is_err() {
+        return vec![String::from("(unreadable)")];
+    }
+    if bytes.contains(&0) {
+        return vec![String::from("(binary file)")];
+    }
+    String::from_utf8_lossy(&bytes)
+        .lines()
+        .map(|line| line.replace('\t', "    "))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn finder(files: &[&str]) -> Finder {
+        Finder::new(Path::new("/nonexistent"), files.iter().map(|file| file.to_string()).collect())
+    }
+
+    fn ranked(finder: &Finder) -> Vec<&str> {
+        finder.matches().map(|(file, _)| file).collect()
+    }
+
+    #[test]
+    fn empty_query_lists_everything_in_order() {
+        let finder = finder(&["a.rs", "b/c.rs", "d.md"]);
+        assert_eq!(ranked(&finder), vec!["a.rs", "b/c.rs", "d.md"]);
+    }
+
+    #[test]
+    fn closer_matches_rank_first() {
+        let mut finder = finder(&["src/document.rs", "docs/main.md", "src/doc.rs", "README.md"]);
+        finder.filter("doc.rs");
+        assert_eq!(ranked(&finder)[0], "src/doc.rs");
+        assert!(!ranked(&finder).contains(&"README.md"));
+        finder.filter("srcdoc");
+        assert_eq!(ranked(&finder), vec!["src/doc.rs", "src/document.rs"]);
+    }
+
+    #[test]
+    fn selection_stays_within_matches() {
+        let mut finder = finder(&["a", "b", "c"]);
+        finder.move_selection(5);
+        assert_eq!(finder.selected(), 2);
+        finder.move_selection(-1);
+        assert_eq!(finder.selected_path(), Some(PathBuf::from("/nonexistent/b")));
+        finder.filter("zzz");
+        assert_eq!(finder.selected_path(), None);
+    }
+}
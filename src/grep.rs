@@ -0,0 +1,167 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::sync::Arc;
+use std::thread;
+use std::time::Instant;
+use ignore::{WalkBuilder, WalkState};
+use crate::search::Search;
+
+const MAX_RESULTS: usize = 10_000;
+const BINARY_CHECK_LEN: usize = 8 * 1024;
+
+// a match found by `grep`: `line` and `column` count from 0, the column in
+// bytes of the line
+pub struct Location {
+    pub path: PathBuf,
+    pub line: usize,
+    pub column: usize,
+    pub text: String, //the line the match starts on
+}
+
+// a search of the files under `root` running in the background
+pub struct Grep {
+    pub root: PathBuf,
+    started: Instant,
+    files: Arc<AtomicUsize>, //files searched so far
+    cancelled: Arc<AtomicBool>,
+    results: Receiver<Vec<Location>>,
+}
+
+impl Grep {
+    pub fn spawn(root: PathBuf, search: Search) -> Self {
+        let files = Arc::new(AtomicUsize::new(0));
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let (sender, results) = mpsc::channel();
+        let (walk_root, walk_files, walk_cancelled) = (root.clone(), Arc::clone(&files), Arc::clone(&cancelled));
+        thread::spawn(move || {
+            let locations = grep(&walk_root, &search, &walk_files, &walk_cancelled);
+            sender.send(locations).ok();
+        });
+        Self {
+            root,
+            started: Instant::now(),
+            files,
+            cancelled,
+            results,
+        }
+    }
+
+    pub fn started(&self) -> Instant {
+        self.started
+    }
+
+    pub fn files(&self) -> usize {
+        self.files.load(Ordering::Relaxed)
+    }
+
+    // stops the search at the next file
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    // the matches once the search is done; a search thread that died found
+    // nothing
+    pub fn results(&self) -> Option<Vec<Location>> {
+        match self.results.try_recv() {
+            Ok(locations) => Some(locations),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => Some(Vec::new()),
+        }
+    }
+}
+
+// the closest directory above `path` holding a `.git`, or the directory of
+// `path` itself when it is not in a repository
+pub fn project_root(path: &Path) -> PathBuf {
+    let dir = if path.is_dir() {
+        path.to_path_buf()
+    }
+    else {
+        path.parent().map_or_else(PathBuf::new, Path::to_path_buf)
+    };
+    let dir = fs::canonicalize(if dir.as_os_str().is_empty() { Path::new(".") } else { &dir })
+        .unwrap_or(dir);
+    dir.ancestors()
+        .find(|ancestor| ancestor.join(".git").exists())
+        .map_or_else(|| dir.clone(), Path::to_path_buf)
+}
+
+// searches the files under `root` on all cores, skipping what .gitignore and
+// friends ignore as well as hidden and binary files, counting them in `files`
+// until `cancelled` is set; the results are sorted by path and line, and stop
+// at MAX_RESULTS
+fn grep(root: &Path, search: &Search, files: &AtomicUsize, cancelled: &AtomicBool) -> Vec<Location> {
+    let (sender, receiver) = mpsc::channel();
+    let found = AtomicUsize::new(0);
+    WalkBuilder::new(root)
+        .require_git(false)
+        .build_parallel()
+        .run(|| {
+            let sender = sender.clone();
+            let found = &found;
+            Box::new(move |entry| {
+                if cancelled.load(Ordering::Relaxed) {
+                    return WalkState::Quit;
+                }
+                let entry = match entry {
+                    Ok(entry) => entry,
+                    Err(_) => return WalkState::Continue,
+                };
+                if !entry.file_type().map_or(false, |file_type| file_type.is_file()) {
+                    return WalkState::Continue;
+                }
+                let locations = grep_file(entry.path(), search);
+                files.fetch_add(1, Ordering::Relaxed);
+                if found.fetch_add(locations.len(), Ordering::Relaxed) >= MAX_RESULTS {
+                    return WalkState::Quit;
+                }
+                for location in locations {
+                    sender.send(location).ok();
+                }
+                WalkState::Continue
+            })
+        });
+    drop(sender);
+    let mut locations: Vec<Location> = receiver.into_iter().collect();
+    locations.sort_by(|a, b| (&a.path, a.line, a.column).cmp(&(&b.path, b.line, b.column)));
+    locations.truncate(MAX_RESULTS);
+    locations
+}
+
+fn grep_file(path: &Path, search: &Search) -> Vec<Location> {
+    let bytes = match fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(_) => return Vec::new(),
+    };
+    if bytes[..bytes.len().min(BINARY_CHECK_LEN)].contains(&0) {
+        return Vec::new();
+    }
+    let text = match String::from_utf8(bytes) {
+        Ok(text) => text,
+        Err(_) => return Vec::new(),
+    };
+    let mut locations = Vec::new();
+    let mut line = 0;
+    let mut counted = 0; //bytes of the text whose line breaks are in `line`
+    let mut last_line = None;
+    for (start, _) in search.matches(&text, 0, text.len()) {
+        line += text[counted..start].matches('\n').count();
+        counted = start;
+        // one result per line is enough to get there
+        if last_line == Some(line) {
+            continue;
+        }
+        last_line = Some(line);
+        let line_start = text[..start].rfind('\n').map_or(0, |idx| idx + 1);
+        let line_end = text[start..].find('\n').map_or(text.len(), |idx| start + idx);
+        locations.push(Location {
+            path: path.to_path_buf(),
+            line,
+            column: start - line_start,
+            text: text[line_start..line_end].trim_end_matches('\r').to_string(),
+        });
+    }
+    locations
+}
@@ -0,0 +1,210 @@
+use crate::Position;
+
+// rows [start, start + before.len()) were replaced by `after`
+pub struct Change {
+    pub start: usize,
+    pub before: Vec<String>,
+    pub after: Vec<String>,
+    pub cursor: Position,
+}
+
+pub struct History {
+    undo: Vec<Vec<Change>>,
+    redo: Vec<Vec<Change>>,
+    open: bool,
+    group: Option<Vec<Change>>, //changes collected into a single step
+    group_depth: usize, //groups begun inside a group end with the outermost one
+    saved: Option<usize>, //undo steps when the document was last saved, none once that state is gone
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self {
+            undo: Vec::new(),
+            redo: Vec::new(),
+            open: false,
+            group: None,
+            group_depth: 0,
+            saved: Some(0),
+        }
+    }
+}
+
+impl Change {
+    fn is_single_row(&self) -> bool {
+        self.before.len() == 1 && self.after.len() == 1
+    }
+}
+
+impl History {
+    // consecutive single-row edits on the same row are merged into one undo step
+    pub fn push(&mut self, change: Change) {
+        self.redo.clear();
+        if let Some(group) = self.group.as_mut() {
+            group.push(change);
+            return;
+        }
+        if self.open && change.is_single_row() {
+            if let Some(step) = self.undo.last_mut() {
+                if let [last] = step.as_mut_slice() {
+                    if last.is_single_row() && last.start == change.start {
+                        last.after = change.after;
+                        self.forget_saved(true);
+                        return;
+                    }
+                }
+            }
+        }
+        self.forget_saved(false);
+        self.undo.push(vec![change]);
+        self.open = true;
+    }
+
+    // collects the changes pushed until the matching `end_group` into one
+    // undo step
+    pub fn begin_group(&mut self) {
+        if self.group.is_none() {
+            self.group = Some(Vec::new());
+        }
+        self.group_depth += 1;
+    }
+
+    // a group of single-row edits on the same rows as the step before it is
+    // merged into that step, as single edits are
+    pub fn end_group(&mut self) {
+        self.group_depth = self.group_depth.saturating_sub(1);
+        if self.group_depth > 0 {
+            return;
+        }
+        let step = match self.group.take() {
+            Some(step) if !step.is_empty() => step,
+            _ => return,
+        };
+        if self.open && step.iter().all(Change::is_single_row) {
+            if let Some(last) = self.undo.last_mut() {
+                let same_rows = last.len() == step.len()
+                    && last.iter().zip(&step).all(|(a, b)| a.is_single_row() && a.start == b.start);
+                if same_rows {
+                    for (last, change) in last.iter_mut().zip(step) {
+                        last.after = change.after;
+                    }
+                    self.forget_saved(true);
+                    return;
+                }
+            }
+        }
+        self.forget_saved(false);
+        self.undo.push(step);
+        self.open = true;
+    }
+
+    // the saved state can no longer be reached once the step it ended with
+    // changes, or once the redo steps leading back to it are dropped
+    fn forget_saved(&mut self, merging: bool) {
+        let len = self.undo.len();
+        if self.saved.map_or(false, |saved| saved > len || (merging && saved == len)) {
+            self.saved = None;
+        }
+    }
+
+    // stops the next change from being merged into the current step
+    pub fn seal(&mut self) {
+        self.open = false;
+    }
+
+    pub fn mark_saved(&mut self) {
+        self.saved = Some(self.undo.len());
+        self.open = false;
+    }
+
+    // no state of the history matches the file any more
+    pub fn mark_unsaved(&mut self) {
+        self.saved = None;
+    }
+
+    // whether undo and redo have brought the text back to how it was saved
+    pub fn is_saved(&self) -> bool {
+        self.saved == Some(self.undo.len())
+    }
+
+    pub fn pop_undo(&mut self) -> Option<Vec<Change>> {
+        self.open = false;
+        self.undo.pop()
+    }
+
+    pub fn pop_redo(&mut self) -> Option<Vec<Change>> {
+        self.open = false;
+        self.redo.pop()
+    }
+
+    pub fn push_undo(&mut self, step: Vec<Change>) {
+        self.undo.push(step);
+    }
+
+    pub fn push_redo(&mut self, step: Vec<Change>) {
+        self.redo.push(step);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn change(start: usize, before: &[&str], after: &[&str]) -> Change {
+        Change {
+            start,
+            before: before.iter().map(|text| text.to_string()).collect(),
+            after: after.iter().map(|text| text.to_string()).collect(),
+            cursor: Position::default(),
+        }
+    }
+
+    #[test]
+    fn typing_on_one_row_is_one_step() {
+        let mut history = History::default();
+        history.push(change(0, &["a"], &["ab"]));
+        history.push(change(0, &["ab"], &["abc"]));
+        history.push(change(1, &["x"], &["xy"]));
+        let step = history.pop_undo().unwrap();
+        assert_eq!(step[0].start, 1);
+        let step = history.pop_undo().unwrap();
+        assert_eq!((step[0].before.clone(), step[0].after.clone()), (vec!["a".to_string()], vec!["abc".to_string()]));
+        assert!(history.pop_undo().is_none());
+    }
+
+    #[test]
+    fn nested_groups_make_one_step() {
+        let mut history = History::default();
+        history.begin_group();
+        history.push(change(0, &["a"], &["a", ""]));
+        history.begin_group();
+        history.push(change(1, &[""], &["  "]));
+        history.end_group();
+        history.push(change(3, &["b"], &["bc"]));
+        history.end_group();
+        assert_eq!(history.pop_undo().map(|step| step.len()), Some(3));
+        assert!(history.pop_undo().is_none());
+    }
+
+    #[test]
+    fn saved_state_is_found_again_by_undo_and_redo() {
+        let mut history = History::default();
+        assert!(history.is_saved());
+        history.push(change(0, &["a"], &["ab"]));
+        assert!(!history.is_saved());
+        history.mark_saved();
+        history.push(change(0, &["ab"], &["abc"]));
+        assert!(!history.is_saved());
+        let step = history.pop_undo().unwrap();
+        history.push_redo(step);
+        assert!(history.is_saved());
+        let step = history.pop_undo().unwrap();
+        history.push_redo(step);
+        assert!(!history.is_saved());
+        // a new edit drops the redo steps back to the saved text
+        history.push(change(0, &["a"], &["ax"]));
+        let step = history.pop_undo().unwrap();
+        history.push_redo(step);
+        assert!(!history.is_saved());
+    }
+}
@@ -0,0 +1,219 @@
+use memmap2::Mmap;
+use std::fs::File;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::Instant;
+
+const INDEX_CHUNK_SIZE: usize = 8 * 1024 * 1024;
+const CHECKPOINT_LINES: usize = 64;
+
+// a memory-mapped file whose line offsets are indexed by a background thread;
+// rows are decoded from the mapping only when they are asked for
+pub struct LargeFile {
+    map: Arc<Mmap>,
+    index: Arc<RwLock<LineIndex>>,
+    indexed: Arc<AtomicUsize>,
+    done: Arc<AtomicBool>,
+    started: Instant,
+}
+
+// only every CHECKPOINT_LINES-th line start is kept, so the index of a file
+// with many short lines stays small; the lines in between are found by
+// scanning on from the checkpoint before them
+struct LineIndex {
+    checkpoints: Vec<usize>,
+    lines: usize, //line starts found so far
+}
+
+struct DoneGuard(Arc<AtomicBool>);
+
+impl Drop for DoneGuard {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::Release);
+    }
+}
+
+impl LargeFile {
+    pub fn open(filename: &str) -> Result<Self, std::io::Error> {
+        let file = File::open(filename)?;
+        // the mapping is only ever read; if another process truncates the file
+        // while it is open, reads past the new end will fault, as with any mmap
+        let map = Arc::new(unsafe { Mmap::map(&file)? });
+        let index = Arc::new(RwLock::new(LineIndex {
+            checkpoints: vec![0],
+            lines: 1,
+        }));
+        let indexed = Arc::new(AtomicUsize::new(0));
+        let done = Arc::new(AtomicBool::new(false));
+        let large_file = Self {
+            map: Arc::clone(&map),
+            index: Arc::clone(&index),
+            indexed: Arc::clone(&indexed),
+            done: Arc::clone(&done),
+            started: Instant::now(),
+        };
+        thread::spawn(move || {
+            // set even if indexing panics, so the editor stops waiting for it
+            let _done = DoneGuard(done);
+            let mut offset = 0;
+            let mut lines = 1;
+            while offset < map.len() {
+                // stop early once the document has been dropped
+                if Arc::strong_count(&index) == 1 {
+                    return;
+                }
+                let end = map.len().min(offset.saturating_add(INDEX_CHUNK_SIZE));
+                let mut checkpoints = Vec::new();
+                for (idx, _) in map[offset..end].iter().enumerate().filter(|(_, byte)| **byte == b'\n') {
+                    let start = offset + idx + 1;
+                    if start >= map.len() {
+                        break;
+                    }
+                    if lines % CHECKPOINT_LINES == 0 {
+                        checkpoints.push(start);
+                    }
+                    lines += 1;
+                }
+                if let Ok(mut index) = index.write() {
+                    index.checkpoints.extend(checkpoints);
+                    index.lines = lines;
+                }
+                offset = end;
+                indexed.store(offset, Ordering::Release);
+            }
+        });
+        Ok(large_file)
+    }
+
+    pub fn is_indexing(&self) -> bool {
+        !self.done.load(Ordering::Acquire)
+    }
+
+    pub fn started(&self) -> Instant {
+        self.started
+    }
+
+    // percentage of the file scanned for line breaks
+    pub fn progress(&self) -> usize {
+        if self.map.is_empty() {
+            return 100;
+        }
+        self.indexed.load(Ordering::Acquire).saturating_mul(100) / self.map.len()
+    }
+
+    // number of rows known so far; the last one only counts once indexing is done
+    pub fn len(&self) -> usize {
+        if self.map.is_empty() {
+            return 0;
+        }
+        let lines = self.index.read().map_or(0, |index| index.lines);
+        if self.is_indexing() {
+            lines.saturating_sub(1)
+        }
+        else {
+            lines
+        }
+    }
+
+    // the whole file as text, if it is valid UTF-8
+    pub fn text(&self) -> Option<&str> {
+        std::str::from_utf8(&self.map).ok()
+    }
+
+    // byte offset where row `y` starts, or the file length past the last row
+    pub fn line_start(&self, y: usize) -> usize {
+        let checkpoint = match self.index.read() {
+            Ok(index) if y < index.lines => index.checkpoints.get(y / CHECKPOINT_LINES).copied(),
+            _ => None,
+        };
+        let mut start = match checkpoint {
+            Some(start) => start,
+            None => return self.map.len(),
+        };
+        for _ in 0..y % CHECKPOINT_LINES {
+            start = self.line_end(start) + 1;
+        }
+        start
+    }
+
+    // the row holding byte `byte`
+    pub fn line_of(&self, byte: usize) -> usize {
+        let (idx, checkpoint, lines) = match self.index.read() {
+            Ok(index) => {
+                let idx = index.checkpoints.partition_point(|start| *start <= byte).saturating_sub(1);
+                (idx, index.checkpoints.get(idx).copied().unwrap_or(0), index.lines)
+            },
+            Err(_) => return 0,
+        };
+        let end = byte.min(self.map.len());
+        let breaks = self.map[checkpoint.min(end)..end].iter().filter(|byte| **byte == b'\n').count();
+        (idx * CHECKPOINT_LINES + breaks).min(lines.saturating_sub(1))
+    }
+
+    pub fn row_text(&self, y: usize) -> Option<String> {
+        if y >= self.len() {
+            return None;
+        }
+        let start = self.line_start(y);
+        let mut line = &self.map[start..self.line_end(start)];
+        if let Some(stripped) = line.strip_suffix(b"\r") {
+            line = stripped;
+        }
+        Some(String::from_utf8_lossy(line).into_owned())
+    }
+
+    // the offset of the line break ending the line that starts at `start`, or
+    // the file length for the last line
+    fn line_end(&self, start: usize) -> usize {
+        self.map[start..]
+            .iter()
+            .position(|byte| *byte == b'\n')
+            .map_or(self.map.len(), |idx| start + idx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::time::Duration;
+
+    fn indexed(name: &str, contents: &str) -> LargeFile {
+        let path = std::env::temp_dir().join(format!("hecto-large-{}-{}", std::process::id(), name));
+        fs::write(&path, contents).unwrap();
+        let large_file = LargeFile::open(path.to_str().unwrap()).unwrap();
+        while large_file.is_indexing() {
+            thread::sleep(Duration::from_millis(1));
+        }
+        fs::remove_file(path).ok();
+        large_file
+    }
+
+    #[test]
+    fn rows_are_found_between_checkpoints() {
+        let contents: String = (0..200).map(|n| format!("line {}\r\n", n)).collect();
+        let large_file = indexed("rows", &contents);
+        assert_eq!(large_file.len(), 200);
+        assert_eq!(large_file.progress(), 100);
+        for y in [0, 1, 63, 64, 65, 130, 199] {
+            assert_eq!(large_file.row_text(y).as_deref(), Some(format!("line {}", y).as_str()));
+            let start = large_file.line_start(y);
+            assert_eq!(contents[..start].matches('\n').count(), y);
+            assert_eq!(large_file.line_of(start), y);
+            assert_eq!(large_file.line_of(start + 3), y);
+        }
+        assert_eq!(large_file.row_text(200), None);
+        assert_eq!(large_file.line_start(200), contents.len());
+        assert_eq!(large_file.line_of(contents.len()), 199);
+    }
+
+    #[test]
+    fn the_last_row_needs_no_line_break() {
+        let large_file = indexed("last", "a\n\nb");
+        assert_eq!(large_file.len(), 3);
+        assert_eq!(large_file.row_text(1).as_deref(), Some(""));
+        assert_eq!(large_file.row_text(2).as_deref(), Some("b"));
+        assert_eq!(indexed("empty", "").len(), 0);
+    }
+}
@@ -0,0 +1,38 @@
+#![warn(clippy::all, clippy::pedantic, clippy::restriction)]
+#![allow(
+    clippy::missing_docs_in_private_items,
+    clippy::implicit_return,
+    clippy::shadow_reuse,
+    clippy::print_stdout,
+    clippy::wildcard_enum_match_arm,
+    clippy::else_if_without_else
+)]
+mod editor;
+mod terminal;
+mod backend;
+mod row;
+mod document;
+mod filetype;
+mod config;
+mod history;
+mod large_file;
+mod screen;
+mod swap;
+mod crash;
+mod disk;
+mod cli;
+mod search;
+mod grep;
+mod finder;
+mod browser;
+mod macros;
+pub use editor::Editor;
+pub use cli::{Args, Command, USAGE};
+pub use terminal::Terminal;
+pub use backend::{Backend, CrosstermBackend, HeadlessBackend};
+pub use editor::Position;
+pub use row::Row;
+pub use document::Document;
+pub use filetype::FileType;
+pub use config::Config;
+pub use screen::Screen;
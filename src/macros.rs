@@ -0,0 +1,134 @@
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use crate::Config;
+
+const MODIFIERS: [(KeyModifiers, &str); 3] = [
+    (KeyModifiers::CONTROL, "C-"),
+    (KeyModifiers::ALT, "M-"),
+    (KeyModifiers::SHIFT, "S-"),
+];
+const NAMED_KEYS: [(&str, KeyCode); 15] = [
+    ("Enter", KeyCode::Enter),
+    ("Tab", KeyCode::Tab),
+    ("BackTab", KeyCode::BackTab),
+    ("Backspace", KeyCode::Backspace),
+    ("Delete", KeyCode::Delete),
+    ("Insert", KeyCode::Insert),
+    ("Esc", KeyCode::Esc),
+    ("Left", KeyCode::Left),
+    ("Right", KeyCode::Right),
+    ("Up", KeyCode::Up),
+    ("Down", KeyCode::Down),
+    ("Home", KeyCode::Home),
+    ("End", KeyCode::End),
+    ("PageUp", KeyCode::PageUp),
+    ("PageDown", KeyCode::PageDown),
+];
+
+// saves `keys` as the macro `name` in the config directory, replacing any
+// macro of that name
+pub fn save(name: &str, keys: &[KeyEvent]) -> Result<PathBuf, std::io::Error> {
+    let path = path(name)?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(&path, encode(keys))?;
+    Ok(path)
+}
+
+pub fn load(name: &str) -> Result<Vec<KeyEvent>, std::io::Error> {
+    decode(&fs::read_to_string(path(name)?)?).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+// named macros are kept one per file, so the name has to be a file name
+fn path(name: &str) -> Result<PathBuf, std::io::Error> {
+    if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\']) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "not a valid macro name"));
+    }
+    Config::dir()
+        .map(|dir| dir.join("macros").join(name))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no config directory"))
+}
+
+// one key per line, such as `x`, `Space`, `C-s`, `M-S-Up` or `F3`; keys
+// with no name are left out
+pub fn encode(keys: &[KeyEvent]) -> String {
+    keys.iter()
+        .filter_map(key_name)
+        .map(|name| format!("{}\n", name))
+        .collect()
+}
+
+pub fn decode(text: &str) -> Result<Vec<KeyEvent>, String> {
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.is_empty())
+        .map(|(idx, line)| parse_key(line).ok_or_else(|| format!("line {}: unknown key {}", idx + 1, line)))
+        .collect()
+}
+
+fn key_name(key: &KeyEvent) -> Option<String> {
+    let code = match key.code {
+        KeyCode::Char(' ') => String::from("Space"),
+        KeyCode::Char(c) => c.to_string(),
+        KeyCode::F(n) => format!("F{}", n),
+        code => NAMED_KEYS.iter().find(|(_, named)| *named == code)?.0.to_string(),
+    };
+    let prefixes: String = MODIFIERS
+        .iter()
+        .filter(|(modifier, _)| key.modifiers.contains(*modifier))
+        .map(|(_, prefix)| *prefix)
+        .collect();
+    Some(format!("{}{}", prefixes, code))
+}
+
+fn parse_key(line: &str) -> Option<KeyEvent> {
+    let mut modifiers = KeyModifiers::NONE;
+    let mut rest = line;
+    // a prefix followed by nothing is the key itself, as in `C-`
+    while let Some((modifier, prefix)) = MODIFIERS
+        .iter()
+        .find(|(_, prefix)| rest.starts_with(prefix) && rest.len() > prefix.len())
+    {
+        modifiers |= *modifier;
+        rest = &rest[prefix.len()..];
+    }
+    let mut chars = rest.chars();
+    let code = match (chars.next(), chars.next()) {
+        (Some(c), None) => KeyCode::Char(c),
+        _ if rest == "Space" => KeyCode::Char(' '),
+        _ => match rest.strip_prefix('F').and_then(|n| n.parse::<u8>().ok()) {
+            Some(n) => KeyCode::F(n),
+            None => NAMED_KEYS.iter().find(|(name, _)| *name == rest)?.1,
+        },
+    };
+    Some(KeyEvent::new(code, modifiers))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_survive_a_round_trip() {
+        let keys = vec![
+            KeyEvent::new(KeyCode::Char('x'), KeyModifiers::NONE),
+            KeyEvent::new(KeyCode::Char(' '), KeyModifiers::NONE),
+            KeyEvent::new(KeyCode::Char('s'), KeyModifiers::CONTROL),
+            KeyEvent::new(KeyCode::Char('-'), KeyModifiers::CONTROL),
+            KeyEvent::new(KeyCode::Up, KeyModifiers::ALT | KeyModifiers::SHIFT),
+            KeyEvent::new(KeyCode::F(3), KeyModifiers::SHIFT),
+            KeyEvent::new(KeyCode::Enter, KeyModifiers::NONE),
+        ];
+        let text = encode(&keys);
+        assert_eq!(text, "x\nSpace\nC-s\nC--\nM-S-Up\nS-F3\nEnter\n");
+        assert_eq!(decode(&text), Ok(keys));
+    }
+
+    #[test]
+    fn unknown_keys_are_reported() {
+        assert_eq!(decode("a\nC-Nope\n").err().as_deref(), Some("line 2: unknown key C-Nope"));
+    }
+}
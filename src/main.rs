@@ -7,17 +7,33 @@
     clippy::wildcard_enum_match_arm,
     clippy::else_if_without_else
 )]
-mod editor;
-mod terminal;
-mod row;
-mod document;
-use editor::Editor;
-pub use terminal::Terminal;
-pub use editor::Position;
-pub use row::Row;
-pub use document::Document;
+use std::env;
+use std::process;
+use hecto::{Command, Editor, USAGE};
+
+const VERSION: &str = env!("CARGO_PKG_VERSION");
 
 fn main() {
-    
-    Editor::default().run();
+    let args = match Command::parse(env::args().skip(1)) {
+        Ok(Command::Run(args)) => args,
+        Ok(Command::Help) => {
+            print!("{}", USAGE);
+            return;
+        },
+        Ok(Command::Version) => {
+            println!("hecto {}", VERSION);
+            return;
+        },
+        Err(err) => {
+            eprintln!("hecto: {}\nTry 'hecto --help' for more information.", err);
+            process::exit(2);
+        },
+    };
+    match Editor::from_args(&args) {
+        Ok(mut editor) => editor.run(),
+        Err(err) => {
+            eprintln!("hecto: {}", err);
+            process::exit(1);
+        },
+    }
 }
@@ -1,110 +1,250 @@
 use std::cmp;
 use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
 
-#[derive(Default)]
+// `boundaries` caches the byte offset where each grapheme starts, followed by
+// the length of the text, so grapheme positions map to bytes in O(1)
+#[derive(Clone)]
 pub struct Row {
     text: String,
-    len: usize,
+    boundaries: Vec<usize>,
+}
+
+impl Default for Row {
+    fn default() -> Self {
+        Self::from("")
+    }
 }
 
 impl From<&str> for Row {
     fn from(value: &str) -> Self {
+        let mut boundaries: Vec<usize> = value.grapheme_indices(true).map(|(idx, _)| idx).collect();
+        boundaries.push(value.len());
         Self {
             text: String::from(value),
-            len: value.graphemes(true).count(),
+            boundaries,
         }
     }
 }
 
 impl Row {
     pub fn render(&self, start: usize, end: usize) -> String {
-        let end = cmp::min(end, self.text.len());
-        let start = cmp::min(start, end);
-        let mut ret: String = String::new();
-
-        for grapheme in self.text[..].graphemes(true).skip(start).take(end - start) {
-            if grapheme == "\t" {
-                ret.push_str(" ");
-            }
-            else {
-                ret.push_str(grapheme);
-            }
-        }
-        ret
+        self.slice(start, end).replace('\t', " ")
     }
 
     pub fn is_empty(&self) -> bool {
-        self.len == 0
+        self.len() == 0
     }
 
     pub fn len(&self) -> usize {
-        self.len
+        self.boundaries.len().saturating_sub(1)
     }
 
     pub fn insert(&mut self, at: usize, c: char) {
-        if at >= self.len() {
-            self.text.push(c);
-            self.len += 1;
-        }
-        else {
-            let mut ret = String::new();
-            let mut length:usize = 0;
-            for (idx, grapheme) in self.text[..].graphemes(true).enumerate() {
-                length += 1;
-                if idx == at {
-                    length += 1;
-                    ret.push(c);
-                }
-                ret.push_str(grapheme);
-            }
-            self.len = length;
-            self.text = ret;
-        }
+        let at = cmp::min(at, self.len());
+        let byte = self.byte_index(at);
+        self.text.insert(byte, c);
+        self.resegment(at.saturating_sub(1), cmp::min(at.saturating_add(1), self.len()), c.len_utf8(), 0);
     }
 
     pub fn delete(&mut self, at: usize) {
         if at < self.len() {
-            let mut ret = String::new();
-            let mut length:usize = 0;
-            for (idx, grapheme) in self.text[..].graphemes(true).enumerate() {
-                if idx != at {
-                    length += 1;
-                    ret.push_str(grapheme);
+            let start = self.byte_index(at);
+            let end = self.byte_index(at.saturating_add(1));
+            self.text.replace_range(start..end, "");
+            self.resegment(at.saturating_sub(1), cmp::min(at.saturating_add(2), self.len()), 0, end - start);
+        }
+    }
+
+    // re-splits graphemes from `first` on after `added` bytes were inserted
+    // into or `removed` bytes deleted from graphemes [first, last). Flags and
+    // emoji sequences can regroup well past the edit, so splitting goes on
+    // until a new boundary falls on one of the old ones again
+    fn resegment(&mut self, first: usize, last: usize, added: usize, removed: usize) {
+        let start = self.boundaries[first];
+        let shift = |boundary: usize| boundary + added - removed;
+        let edit_end = shift(self.boundaries[last]);
+        let mut starts = Vec::new();
+        let mut old = last;
+        let mut resume = self.boundaries.len() - 1; //the end of the text, if nothing lines up
+        for (idx, _) in self.text[start..].grapheme_indices(true) {
+            let byte = start + idx;
+            if byte >= edit_end {
+                while old < self.boundaries.len() && shift(self.boundaries[old]) < byte {
+                    old += 1;
+                }
+                if old < self.boundaries.len() && shift(self.boundaries[old]) == byte {
+                    resume = old;
+                    break;
                 }
             }
-            self.len = length;
-            self.text = ret;
+            starts.push(byte);
+        }
+        for boundary in &mut self.boundaries[resume..] {
+            *boundary = shift(*boundary);
         }
+        self.boundaries.splice(first..resume, starts);
+    }
+
+    // screen columns taken by graphemes [0, at), drawn as `render` draws them
+    pub fn column(&self, at: usize) -> usize {
+        self.render(0, at).width()
     }
 
-    pub fn append(&mut self, append_row: &Self) {
-        self.text = format!("{}{}", self.text, append_row.text);
-        self.len += append_row.len;
+    pub fn width(&self) -> usize {
+        self.column(self.len())
     }
 
-    pub fn split(&mut self, at: usize) -> Self {
-        let mut original_row: String = String::new();
-        let mut length = 0;
-        let mut splitted_row: String = String::new();
-        let mut splitted_length = 0;
-        for (index, grapheme) in self.text[..].graphemes(true).enumerate() {
-            if index < at {
-                length += 1;
-                original_row.push_str(grapheme);
-            } else {
-                splitted_length += 1;
-                splitted_row.push_str(grapheme);
+    // number of graphemes that start before screen column `column`
+    pub fn index_at_column(&self, column: usize) -> usize {
+        let mut width = 0;
+        for (idx, grapheme) in self.text.graphemes(true).enumerate() {
+            if width >= column {
+                return idx;
             }
+            width += if grapheme == "\t" { 1 } else { grapheme.width() };
         }
-        self.text = original_row;
-        self.len = length;
-        Self { 
-            text: splitted_row,
-            len: splitted_length,
-        }
+        self.len()
+    }
+
+    pub fn slice(&self, start: usize, end: usize) -> String {
+        let end = cmp::min(end, self.len());
+        let start = cmp::min(start, end);
+        self.text[self.byte_index(start)..self.byte_index(end)].to_string()
+    }
+
+    pub fn indentation(&self) -> &str {
+        let content = self.text.trim_start_matches(|c| c == ' ' || c == '\t');
+        &self.text[..self.text.len() - content.len()]
+    }
+
+    pub fn is_blank(&self) -> bool {
+        self.text.trim().is_empty()
+    }
+
+    // grapheme index of the start of the word before `at`, or 0
+    pub fn prev_word_start(&self, at: usize) -> usize {
+        let byte = self.byte_index(at);
+        self.text
+            .unicode_word_indices()
+            .map(|(idx, _)| idx)
+            .filter(|idx| *idx < byte)
+            .last()
+            .map_or(0, |idx| self.grapheme_index(idx))
+    }
+
+    // grapheme index of the end of the word after `at`, or the row length
+    pub fn next_word_end(&self, at: usize) -> usize {
+        let byte = self.byte_index(at);
+        self.text
+            .unicode_word_indices()
+            .map(|(idx, word)| idx + word.len())
+            .find(|end| *end > byte)
+            .map_or(self.len(), |end| self.grapheme_index(end))
+    }
+
+    // grapheme range of the word `at` is in or at the end of
+    pub fn word_at(&self, at: usize) -> Option<(usize, usize)> {
+        let byte = self.byte_index(at);
+        self.text
+            .unicode_word_indices()
+            .find(|(idx, word)| *idx <= byte && byte <= idx + word.len())
+            .map(|(idx, word)| (self.grapheme_index(idx), self.grapheme_index(idx + word.len())))
+    }
+
+    // byte offset of grapheme `at`, clamped to the end of the row
+    pub fn byte_index(&self, at: usize) -> usize {
+        self.boundaries.get(at).copied().unwrap_or(self.text.len())
+    }
+
+    // number of graphemes starting before `byte`
+    pub fn grapheme_index(&self, byte: usize) -> usize {
+        cmp::min(self.boundaries.partition_point(|boundary| *boundary < byte), self.len())
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.text
     }
 
     pub fn as_bytes(&self) -> &[u8] {
         self.text.as_bytes()
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the patched boundaries have to match splitting the text afresh
+    fn assert_segmented(row: &Row) {
+        assert_eq!(row.boundaries, Row::from(row.as_str()).boundaries, "{:?}", row.as_str());
+    }
+
+    fn inserted(text: &str, at: usize, c: char) -> Row {
+        let mut row = Row::from(text);
+        row.insert(at, c);
+        assert_segmented(&row);
+        row
+    }
+
+    fn deleted(text: &str, at: usize) -> Row {
+        let mut row = Row::from(text);
+        row.delete(at);
+        assert_segmented(&row);
+        row
+    }
+
+    #[test]
+    fn combining_marks_join_the_letter_before() {
+        assert_eq!(inserted("ex", 1, '\u{301}').len(), 2);
+        assert_eq!(inserted("e\u{301}x", 1, '\u{302}').len(), 2);
+        assert_eq!(deleted("ae\u{301}b", 1).as_str(), "ab");
+        assert_eq!(deleted("ae\u{301}b", 0).len(), 2);
+    }
+
+    #[test]
+    fn zwj_sequences_stay_one_grapheme() {
+        let family = "\u{1f468}\u{200d}\u{1f469}\u{200d}\u{1f467}";
+        assert_eq!(inserted("\u{1f468}\u{200d}", 1, '\u{1f469}').len(), 1);
+        assert_eq!(inserted(family, 1, 'a').len(), 2);
+        assert_eq!(inserted(family, 0, 'a').len(), 2);
+        assert_eq!(deleted(&format!("a{}b", family), 1).as_str(), "ab");
+    }
+
+    #[test]
+    fn flags_regroup_in_pairs() {
+        let us_gb = "\u{1f1fa}\u{1f1f8}\u{1f1ec}\u{1f1e7}";
+        // a lone indicator in front pairs up with the next one, and so on
+        let row = inserted(us_gb, 0, '\u{1f1eb}');
+        assert_eq!(row.len(), 3);
+        assert_eq!(deleted(row.as_str(), 0).as_str(), "\u{1f1f8}\u{1f1ec}\u{1f1e7}");
+        assert_eq!(inserted(us_gb, 2, '\u{1f1eb}').len(), 3);
+        assert_eq!(inserted(&format!("x{}y", us_gb), 3, 'z').len(), 5);
+    }
+
+
+    #[test]
+    fn columns_count_wide_characters_twice() {
+        let row = Row::from("a日\tb");
+        assert_eq!(row.column(2), 3);
+        assert_eq!(row.width(), 5);
+        assert_eq!(row.index_at_column(1), 1);
+        assert_eq!(row.index_at_column(2), 2);
+        assert_eq!(row.index_at_column(3), 2);
+        assert_eq!(row.index_at_column(9), 4);
+    }
+
+    #[test]
+    fn words_are_found_around_the_cursor() {
+        let row = Row::from("let  foo_bar = baz(1);");
+        assert_eq!(row.next_word_end(0), 3);
+        assert_eq!(row.next_word_end(3), 12);
+        assert_eq!(row.next_word_end(18), 20);
+        assert_eq!(row.next_word_end(20), row.len());
+        assert_eq!(row.prev_word_start(12), 5);
+        assert_eq!(row.prev_word_start(5), 0);
+        assert_eq!(row.prev_word_start(0), 0);
+        assert_eq!(row.word_at(7), Some((5, 12)));
+        assert_eq!(row.word_at(12), Some((5, 12)));
+        assert_eq!(row.word_at(13), None);
+    }
+}
@@ -0,0 +1,264 @@
+use crossterm::style::{Color, Colors};
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Cell {
+    pub symbol: String, //empty for the right half of a wide grapheme
+    pub colors: Colors,
+}
+
+impl Default for Cell {
+    fn default() -> Self {
+        Self {
+            symbol: String::from(" "),
+            colors: Colors::new(Color::Reset, Color::Reset),
+        }
+    }
+}
+
+// a run of changed cells starting at (x, y)
+pub struct Run<'a> {
+    pub x: usize,
+    pub y: usize,
+    pub cells: &'a [Cell],
+}
+
+// the frame being drawn, and the frame currently on the terminal; only the
+// cells that differ between the two need to be written out
+#[derive(Default)]
+pub struct Screen {
+    width: usize,
+    height: usize,
+    cells: Vec<Cell>,
+    previous: Vec<Cell>,
+}
+
+impl Screen {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            cells: vec![Cell::default(); width * height],
+            previous: Vec::new(),
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn resize(&mut self, width: usize, height: usize) {
+        *self = Self::new(width, height);
+    }
+
+    pub fn clear(&mut self) {
+        for cell in &mut self.cells {
+            *cell = Cell::default();
+        }
+    }
+
+    // forgets what is on the terminal so the next frame is drawn in full
+    pub fn invalidate(&mut self) {
+        self.previous.clear();
+    }
+
+    pub fn cell(&self, x: usize, y: usize) -> Option<&Cell> {
+        if x < self.width {
+            self.cells.get(y * self.width + x)
+        }
+        else {
+            None
+        }
+    }
+
+    // the symbols of line `y`, as they would appear on the terminal
+    pub fn line(&self, y: usize) -> String {
+        (0..self.width)
+            .filter_map(|x| self.cell(x, y))
+            .map(|cell| cell.symbol.as_str())
+            .collect()
+    }
+
+    // writes `text` at (x, y), clipped to the line; returns the column after it
+    pub fn put_str(&mut self, x: usize, y: usize, text: &str, colors: Colors) -> usize {
+        let mut x = x;
+        if y >= self.height {
+            return x;
+        }
+        for grapheme in text.graphemes(true) {
+            let width = grapheme.width();
+            if width == 0 {
+                continue;
+            }
+            if x.saturating_add(width) > self.width {
+                // a wide grapheme that does not fit is replaced by blanks
+                while x < self.width {
+                    self.set(x, y, " ", colors);
+                    x += 1;
+                }
+                break;
+            }
+            self.set(x, y, grapheme, colors);
+            for column in 1..width {
+                self.set(x + column, y, "", colors);
+            }
+            x += width;
+        }
+        x
+    }
+
+    // fills line `y` with blanks from column `x` to the end
+    pub fn fill(&mut self, x: usize, y: usize, colors: Colors) {
+        for column in x..self.width {
+            self.set(column, y, " ", colors);
+        }
+    }
+
+    fn set(&mut self, x: usize, y: usize, symbol: &str, colors: Colors) {
+        if let Some(cell) = self.cells.get_mut(y * self.width + x) {
+            cell.symbol = String::from(symbol);
+            cell.colors = colors;
+        }
+    }
+
+    // runs of cells that differ from what is on the terminal
+    pub fn changes(&self) -> Vec<Run<'_>> {
+        let mut runs = Vec::new();
+        for y in 0..self.height {
+            let line = &self.cells[y * self.width..(y + 1) * self.width];
+            let previous = self.previous.get(y * self.width..(y + 1) * self.width);
+            let changed = |x: usize| previous.map_or(true, |previous| previous[x] != line[x]);
+            let mut x = 0;
+            while x < self.width {
+                if !changed(x) {
+                    x += 1;
+                    continue;
+                }
+                // start on the wide grapheme itself rather than its right half
+                let mut start = x;
+                while start > 0 && line[start].symbol.is_empty() {
+                    start -= 1;
+                }
+                let mut end = x.saturating_add(1);
+                while end < self.width && (changed(end) || line[end].symbol.is_empty()) {
+                    end += 1;
+                }
+                runs.push(Run {
+                    x: start,
+                    y,
+                    cells: &line[start..end],
+                });
+                x = end;
+            }
+        }
+        runs
+    }
+
+    // records the current frame as being on the terminal
+    pub fn present(&mut self) {
+        self.previous = self.cells.clone();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn colors() -> Colors {
+        Colors::new(Color::Black, Color::White)
+    }
+
+    fn runs(screen: &Screen) -> Vec<(usize, usize, String)> {
+        screen
+            .changes()
+            .iter()
+            .map(|run| (run.x, run.y, run.cells.iter().map(|cell| cell.symbol.as_str()).collect()))
+            .collect()
+    }
+
+    #[test]
+    fn put_str_writes_and_clips() {
+        let mut screen = Screen::new(5, 2);
+        assert_eq!(screen.put_str(1, 0, "abcdef", colors()), 5);
+        assert_eq!(screen.line(0), " abcd");
+        assert_eq!(screen.line(1), "     ");
+        assert_eq!(screen.cell(1, 0).map(|cell| cell.colors), Some(colors()));
+        assert_eq!(screen.cell(0, 0).map(|cell| cell.colors), Some(Cell::default().colors));
+    }
+
+    #[test]
+    fn wide_graphemes_take_two_cells() {
+        let mut screen = Screen::new(5, 1);
+        assert_eq!(screen.put_str(0, 0, "中文x", colors()), 5);
+        assert_eq!(screen.cell(1, 0).map(|cell| cell.symbol.as_str()), Some(""));
+        assert_eq!(screen.line(0), "中文x");
+        screen.clear();
+        assert_eq!(screen.put_str(3, 0, "中文", colors()), 5);
+        assert_eq!(screen.line(0), "   中");
+        screen.clear();
+        assert_eq!(screen.put_str(4, 0, "中", colors()), 5);
+        assert_eq!(screen.line(0), "     ");
+    }
+
+    #[test]
+    fn first_frame_is_drawn_in_full() {
+        let mut screen = Screen::new(3, 2);
+        screen.put_str(0, 0, "ab", colors());
+        assert_eq!(runs(&screen), vec![(0, 0, "ab ".to_string()), (0, 1, "   ".to_string())]);
+    }
+
+    #[test]
+    fn unchanged_frame_has_no_changes() {
+        let mut screen = Screen::new(4, 2);
+        screen.put_str(0, 0, "abcd", colors());
+        screen.present();
+        screen.clear();
+        screen.put_str(0, 0, "abcd", colors());
+        assert!(runs(&screen).is_empty());
+    }
+
+    #[test]
+    fn only_changed_runs_are_reported() {
+        let mut screen = Screen::new(6, 2);
+        screen.put_str(0, 0, "abcdef", colors());
+        screen.put_str(0, 1, "ghijkl", colors());
+        screen.present();
+        screen.clear();
+        screen.put_str(0, 0, "aXcdYZ", colors());
+        screen.put_str(0, 1, "ghijkl", colors());
+        assert_eq!(runs(&screen), vec![(1, 0, "X".to_string()), (4, 0, "YZ".to_string())]);
+    }
+
+    #[test]
+    fn color_changes_are_reported() {
+        let mut screen = Screen::new(3, 1);
+        screen.put_str(0, 0, "abc", colors());
+        screen.present();
+        screen.put_str(1, 0, "b", Colors::new(Color::Black, Color::Yellow));
+        assert_eq!(runs(&screen), vec![(1, 0, "b".to_string())]);
+    }
+
+    #[test]
+    fn runs_include_whole_wide_graphemes() {
+        let mut screen = Screen::new(4, 1);
+        screen.put_str(0, 0, "abcd", colors());
+        screen.present();
+        screen.clear();
+        screen.put_str(0, 0, "a中d", colors());
+        assert_eq!(runs(&screen), vec![(1, 0, "中".to_string())]);
+    }
+
+    #[test]
+    fn invalidate_forces_a_full_redraw() {
+        let mut screen = Screen::new(2, 1);
+        screen.put_str(0, 0, "ab", colors());
+        screen.present();
+        screen.invalidate();
+        assert_eq!(runs(&screen), vec![(0, 0, "ab".to_string())]);
+    }
+}
@@ -0,0 +1,246 @@
+use std::iter;
+use regex::{Captures, Regex, RegexBuilder};
+
+// how the text typed at the search prompt is matched; kept between searches
+#[derive(Default, Clone, Copy)]
+pub struct SearchOptions {
+    pub regex: bool,
+    pub ignore_case: bool,
+    pub whole_word: bool,
+    pub in_selection: bool, //only search the text selected when searching starts
+}
+
+// a compiled search, run over the whole text so that patterns such as
+// `\n\n+` can match across rows; `^` and `$` match at the start and end of
+// every row. Plain patterns are escaped and their replacements are taken
+// literally; in regex mode `$1` or `${name}` in a replacement stands for
+// what that group matched and `\n` or `\t` for a line break or tab
+pub struct Search {
+    regex: Regex,
+    literal: bool,
+}
+
+// the matches replaced in one pass: bytes [start, end) of the text become
+// `text`, and the last replacement ends at byte `cursor` of it
+pub struct Replaced {
+    pub start: usize,
+    pub end: usize,
+    pub text: String,
+    pub count: usize,
+    pub cursor: usize,
+}
+
+impl SearchOptions {
+    pub fn compile(&self, pattern: &str) -> Result<Search, String> {
+        let mut source = if self.regex {
+            pattern.to_string()
+        }
+        else {
+            regex::escape(pattern)
+        };
+        if self.whole_word {
+            source = format!(r"\b(?:{})\b", source);
+        }
+        let regex = RegexBuilder::new(&source)
+            .case_insensitive(self.ignore_case)
+            .multi_line(true)
+            .crlf(true)
+            .build()
+            .map_err(|err| {
+                // the last line of a syntax error says what is wrong, the
+                // ones above point at where
+                let message = err.to_string();
+                let reason = message.lines().last().unwrap_or_default();
+                reason.strip_prefix("error: ").unwrap_or(reason).to_string()
+            })?;
+        Ok(Search {
+            regex,
+            literal: !self.regex,
+        })
+    }
+
+    // the toggles that are on, as shown in the prompt
+    pub fn describe(&self) -> String {
+        let flags: Vec<&str> = [
+            (self.regex, "regex"),
+            (self.ignore_case, "ignore case"),
+            (self.whole_word, "whole word"),
+            (self.in_selection, "in selection"),
+        ]
+        .iter()
+        .filter(|(on, _)| *on)
+        .map(|(_, name)| *name)
+        .collect();
+        if flags.is_empty() {
+            String::new()
+        }
+        else {
+            format!(" ({})", flags.join(", "))
+        }
+    }
+}
+
+impl Search {
+    // byte ranges of the matches that lie within bytes [from, to) of `text`;
+    // the text around them still counts for anchors and word boundaries
+    pub fn matches<'a>(&'a self, text: &'a str, from: usize, to: usize) -> impl Iterator<Item = (usize, usize)> + 'a {
+        let mut at = Some(from);
+        iter::from_fn(move || {
+            let found = self.regex.find_at(text, at?)?;
+            if found.end() > to {
+                return None;
+            }
+            at = next_start(text, found.start(), found.end());
+            Some((found.start(), found.end()))
+        })
+    }
+
+    fn captures<'a>(&'a self, text: &'a str, from: usize, to: usize) -> impl Iterator<Item = Captures<'a>> + 'a {
+        let mut at = Some(from);
+        iter::from_fn(move || {
+            let captures = self.regex.captures_at(text, at?)?;
+            let found = captures.get(0)?;
+            if found.end() > to {
+                return None;
+            }
+            at = next_start(text, found.start(), found.end());
+            Some(captures)
+        })
+    }
+
+    // replaces up to `limit` matches within bytes [from, to) of `text`, all
+    // of them with no limit; None if there are none
+    pub fn replace(&self, text: &str, replacement: &str, from: usize, to: usize, limit: Option<usize>) -> Option<Replaced> {
+        let replacement = if self.literal {
+            replacement.to_string()
+        }
+        else {
+            unescape(replacement)
+        };
+        let mut replaced: Option<Replaced> = None;
+        for captures in self.captures(text, from, to) {
+            let found = match captures.get(0) {
+                Some(found) => found,
+                None => continue,
+            };
+            let done = replaced.get_or_insert_with(|| Replaced {
+                start: found.start(),
+                end: found.start(),
+                text: String::new(),
+                count: 0,
+                cursor: 0,
+            });
+            if limit.map_or(false, |limit| done.count >= limit) {
+                break;
+            }
+            done.text.push_str(&text[done.end..found.start()]);
+            if self.literal {
+                done.text.push_str(&replacement);
+            }
+            else {
+                captures.expand(&replacement, &mut done.text);
+            }
+            done.cursor = done.text.len();
+            done.end = found.end();
+            done.count += 1;
+        }
+        replaced
+    }
+}
+
+// where to look for the match after [start, end); an empty match moves on by
+// one character so the search cannot get stuck on it
+fn next_start(text: &str, start: usize, end: usize) -> Option<usize> {
+    if end > start {
+        return Some(end);
+    }
+    text[end..].chars().next().map(|c| end + c.len_utf8())
+}
+
+// `\n`, `\t` and `\\` in a replacement, which cannot be typed at the prompt
+fn unescape(replacement: &str) -> String {
+    let mut unescaped = String::with_capacity(replacement.len());
+    let mut chars = replacement.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => unescaped.push('\n'),
+            Some('t') => unescaped.push('\t'),
+            Some('\\') => unescaped.push('\\'),
+            Some(other) => {
+                unescaped.push('\\');
+                unescaped.push(other);
+            },
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compile(pattern: &str, regex: bool, ignore_case: bool, whole_word: bool) -> Search {
+        SearchOptions { regex, ignore_case, whole_word, in_selection: false }.compile(pattern).unwrap()
+    }
+
+    fn matches(search: &Search, text: &str) -> Vec<(usize, usize)> {
+        search.matches(text, 0, text.len()).collect()
+    }
+
+    fn replace(search: &Search, text: &str, replacement: &str) -> String {
+        let replaced = search.replace(text, replacement, 0, text.len(), None).unwrap();
+        format!("{}{}{}", &text[..replaced.start], replaced.text, &text[replaced.end..])
+    }
+
+    #[test]
+    fn plain_patterns_are_literal() {
+        let search = compile("a.b", false, false, false);
+        assert_eq!(matches(&search, "axb a.b"), vec![(4, 7)]);
+        assert_eq!(replace(&search, "a.b", "$1"), "$1");
+    }
+
+    #[test]
+    fn capture_groups_in_replacements() {
+        let search = compile(r"(\w+)\.len\(\)", true, false, false);
+        let replaced = search.replace("x = a.len() + bb.len();", "len($1)", 0, 23, None).unwrap();
+        assert_eq!((replaced.start, replaced.end), (4, 22));
+        assert_eq!(replaced.text, "len(a) + len(bb)");
+        assert_eq!(replaced.count, 2);
+        assert_eq!(replaced.cursor, replaced.text.len());
+    }
+
+    #[test]
+    fn case_and_whole_word_toggles() {
+        let search = compile("foo", false, true, true);
+        assert_eq!(matches(&search, "Foo food FOO"), vec![(0, 3), (9, 12)]);
+    }
+
+    #[test]
+    fn replace_within_range_and_limit() {
+        let search = compile("x", false, false, false);
+        let replaced = search.replace("x x x x", "yy", 1, 5, Some(1)).unwrap();
+        assert_eq!((replaced.start, replaced.end, replaced.text.as_str()), (2, 3, "yy"));
+        let replaced = search.replace("x x x x", "yy", 1, 5, None).unwrap();
+        assert_eq!((replaced.start, replaced.end, replaced.text.as_str()), (2, 5, "yy yy"));
+        assert!(search.replace("x x", "y", 1, 2, None).is_none());
+    }
+
+    #[test]
+    fn patterns_span_rows() {
+        let search = compile(r"\n\n+", true, false, false);
+        assert_eq!(replace(&search, "a\n\n\nb\nc\n", r"\n"), "a\nb\nc\n");
+        let search = compile("^b$", true, false, false);
+        assert_eq!(matches(&search, "ab\nb\nbc\n"), vec![(3, 4)]);
+    }
+
+    #[test]
+    fn invalid_patterns_say_why() {
+        let options = SearchOptions { regex: true, ..SearchOptions::default() };
+        assert_eq!(options.compile("(a").err().as_deref(), Some("unclosed group"));
+    }
+}
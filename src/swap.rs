@@ -0,0 +1,167 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command, Stdio};
+use std::time::SystemTime;
+use crate::Position;
+
+const HEADER: &str = "hecto swap";
+
+// unsaved edits are copied to a swap file so that they survive a crash or a
+// dropped connection. The swap file of `dir/name` is `dir/.name.swp`; buffers
+// without a name are kept in the state directory instead
+pub struct Swap {
+    path: PathBuf,
+}
+
+// what a swap file left behind by an earlier session holds
+pub struct Recovery {
+    pub contents: String,
+    pub cursor: Position,
+    pub modified: Option<SystemTime>,
+    pub owner: Option<Owner>, //missing from swap files of older versions
+}
+
+// the editor process that wrote a swap file
+pub struct Owner {
+    pub pid: u32,
+    pub host: String,
+}
+
+impl Owner {
+    fn current() -> Self {
+        Self {
+            pid: process::id(),
+            host: hostname(),
+        }
+    }
+
+    // whether the owner is still editing the file; a process on another host
+    // cannot be checked, so it is taken to be gone
+    pub fn is_running(&self) -> bool {
+        if self.host != hostname() {
+            return false;
+        }
+        if Path::new("/proc/self").exists() {
+            return Path::new("/proc").join(self.pid.to_string()).exists();
+        }
+        Command::new("kill")
+            .args(["-0", &self.pid.to_string()])
+            .stderr(Stdio::null())
+            .status()
+            .map_or(false, |status| status.success())
+    }
+}
+
+fn hostname() -> String {
+    ["/proc/sys/kernel/hostname", "/etc/hostname"]
+        .iter()
+        .find_map(|path| fs::read_to_string(path).ok())
+        .or_else(|| env::var("HOSTNAME").ok())
+        .map(|host| host.trim().to_string())
+        .filter(|host| !host.is_empty() && !host.contains(char::is_whitespace))
+        .unwrap_or_else(|| String::from("localhost"))
+}
+
+impl Swap {
+    pub fn for_file(file_name: Option<&str>) -> Option<Self> {
+        let path = match file_name {
+            Some(file_name) => {
+                let path = Path::new(file_name);
+                let name = path.file_name()?.to_string_lossy();
+                path.with_file_name(format!(".{}.swp", name))
+            },
+            None => Self::state_dir()?.join(format!("unnamed-{}.swp", process::id())),
+        };
+        Some(Self { path })
+    }
+
+    fn state_dir() -> Option<PathBuf> {
+        if let Some(dir) = env::var_os("XDG_STATE_HOME") {
+            return Some(PathBuf::from(dir).join("hecto"));
+        }
+        env::var_os("HOME").map(|home| PathBuf::from(home).join(".local").join("state").join("hecto"))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn exists(&self) -> bool {
+        self.path.is_file()
+    }
+
+    pub fn read(&self) -> Option<Recovery> {
+        let swap = fs::read_to_string(&self.path).ok()?;
+        let (header, contents) = swap.split_once('\n')?;
+        let mut position = header.strip_prefix(HEADER)?.split_whitespace();
+        let x = position.next()?.parse().ok()?;
+        let y = position.next()?.parse().ok()?;
+        let owner = match (position.next(), position.next()) {
+            (Some(pid), Some(host)) => Some(Owner {
+                pid: pid.parse().ok()?,
+                host: host.to_string(),
+            }),
+            _ => None,
+        };
+        Some(Recovery {
+            contents: contents.to_string(),
+            cursor: Position { x, y },
+            modified: fs::metadata(&self.path).and_then(|metadata| metadata.modified()).ok(),
+            owner,
+        })
+    }
+
+    // writes to a temporary file first so that a crash mid-write never leaves
+    // a truncated swap file behind
+    pub fn write(&self, contents: &str, cursor: &Position) -> Result<(), std::io::Error> {
+        if let Some(dir) = self.path.parent() {
+            if !dir.as_os_str().is_empty() {
+                fs::create_dir_all(dir)?;
+            }
+        }
+        let mut temp = self.path.clone().into_os_string();
+        temp.push(".tmp");
+        let owner = Owner::current();
+        fs::write(
+            &temp,
+            format!("{} {} {} {} {}\n{}", HEADER, cursor.x, cursor.y, owner.pid, owner.host, contents),
+        )?;
+        fs::rename(&temp, &self.path)
+    }
+
+    pub fn remove(&self) {
+        fs::remove_file(&self.path).ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn swap_files_name_the_process_writing_them() {
+        let path = env::temp_dir().join(format!("hecto-swap-{}", process::id()));
+        let swap = Swap { path: path.clone() };
+        swap.write("one\ntwo\n", &Position { x: 1, y: 2 }).unwrap();
+        let recovery = swap.read().unwrap();
+        swap.remove();
+        assert_eq!(recovery.contents, "one\ntwo\n");
+        assert_eq!((recovery.cursor.x, recovery.cursor.y), (1, 2));
+        let owner = recovery.owner.unwrap();
+        assert_eq!(owner.pid, process::id());
+        assert!(owner.is_running());
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn owners_on_other_hosts_or_from_older_versions_are_not_running() {
+        let path = env::temp_dir().join(format!("hecto-swap-old-{}", process::id()));
+        let swap = Swap { path };
+        fs::write(swap.path(), "hecto swap 0 0\ntext\n").unwrap();
+        assert!(swap.read().unwrap().owner.is_none());
+        fs::write(swap.path(), format!("hecto swap 0 0 {} {}-elsewhere\ntext\n", process::id(), hostname())).unwrap();
+        assert!(!swap.read().unwrap().owner.unwrap().is_running());
+        swap.remove();
+    }
+}
@@ -1,11 +1,8 @@
-use std::io::{stdout, Write};
-use crossterm::{
-    event::{Event, read},
-    terminal, ExecutableCommand,
-    cursor, QueueableCommand,
-    style::{Colors, SetColors, ResetColor},
-};
+use std::time::Duration;
+use crossterm::event::Event;
+use crate::backend::{Backend, CrosstermBackend};
 use crate::Position;
+use crate::Screen;
 
 pub struct Size {
     pub width: u16,
@@ -14,73 +11,126 @@ pub struct Size {
 
 pub struct Terminal {
     pub size: Size,
+    backend: Box<dyn Backend>,
+    alternate_screen: bool, //the editor draws on the alternate screen, leaving the shell's intact
 }
 
 impl Terminal {
-    pub fn default() -> Result<Self, std::io::Error> {
-        let size = terminal::size().unwrap();
-        terminal::enable_raw_mode().ok();
+    pub fn default(alternate_screen: bool) -> Result<Self, std::io::Error> {
+        Self::with_backend(Box::new(CrosstermBackend), alternate_screen)
+    }
+
+    pub fn with_backend(mut backend: Box<dyn Backend>, alternate_screen: bool) -> Result<Self, std::io::Error> {
+        let size = backend.size()?;
+        if alternate_screen {
+            backend.enter_alternate_screen()?;
+        }
+        backend.enable_raw_mode().ok();
         Ok(Self {
             size: Size { width: size.0, height: size.1.saturating_sub(2) },
+            backend,
+            alternate_screen,
         })
     }
 
-    pub fn clear_screen() {
-        stdout().execute(terminal::Clear(terminal::ClearType::All)).ok();
+    pub fn clear_screen(&mut self) {
+        self.backend.clear_screen().ok();
+        self.backend.flush().ok();
     }
 
-    pub fn clear_current_line() {
-        stdout().execute(terminal::Clear(terminal::ClearType::CurrentLine)).ok();
+    pub fn flush(&mut self) -> Result<(), std::io::Error> {
+        self.backend.flush()
     }
 
-    pub fn flush() -> Result<(), std::io::Error> {
-        stdout().flush()
+    pub fn quit(&mut self) {
+        self.clear_screen();
+        if self.alternate_screen {
+            self.backend.leave_alternate_screen().ok();
+        }
+        self.backend.disable_raw_mode().ok();
+        self.backend.set_cursor_visible(true).ok();
+        self.reset_colors();
+        //println!("now quit hecto");
     }
 
-    pub fn quit() {
-        Self::clear_screen();
-        terminal::disable_raw_mode().ok();
-        Self::reset_colors();
-        //println!("now quit hecto");
+    // hands the terminal back to the shell while the process is stopped, then
+    // takes it over again
+    pub fn suspend(&mut self) -> Result<(), std::io::Error> {
+        self.release()?;
+        self.backend.suspend()?;
+        self.reclaim()
+    }
+
+    // puts the terminal back in its normal mode, e.g. while another program
+    // talks to the user
+    pub fn release(&mut self) -> Result<(), std::io::Error> {
+        if self.alternate_screen {
+            self.backend.leave_alternate_screen()?;
+        }
+        else {
+            self.backend.clear_screen()?;
+            self.backend.set_cursor_position(0, 0)?;
+        }
+        self.backend.set_cursor_visible(true)?;
+        self.backend.reset_colors()?;
+        self.backend.flush()?;
+        self.backend.disable_raw_mode()
     }
 
-    pub fn read() -> Result<Event, std::io::Error> {
-        loop {
-            let event = read();
-            return event;
+    // takes the terminal over again after `release`; the size is queried anew
+    // as it may have changed in the meantime
+    pub fn reclaim(&mut self) -> Result<(), std::io::Error> {
+        if self.alternate_screen {
+            self.backend.enter_alternate_screen()?;
         }
+        self.backend.enable_raw_mode()?;
+        let size = self.backend.size()?;
+        self.size = Size { width: size.0, height: size.1.saturating_sub(2) };
+        Ok(())
+    }
+
+    pub fn read(&mut self) -> Result<Event, std::io::Error> {
+        self.backend.read()
+    }
+
+    pub fn poll(&mut self, timeout: Duration) -> Result<bool, std::io::Error> {
+        self.backend.poll(timeout)
     }
 
-    pub fn set_cursor_position(pos: &Position) {
-        let Position {mut x, mut y} = pos;
-        x = x.saturating_add(1);
-        y = y.saturating_add(1);
-        let x = x as u16;
-        let y = y as u16;
-        stdout().queue(cursor::MoveTo(x-1,y-1)).ok();
+    pub fn set_cursor_position(&mut self, pos: &Position) {
+        let x = pos.x as u16;
+        let y = pos.y as u16;
+        self.backend.set_cursor_position(x, y).ok();
     }
 
-    pub fn cursor_hide() {
-        stdout().execute(cursor::Hide).ok();
+    // queues the cells that changed since the last frame; nothing is written
+    // until `flush`
+    pub fn draw(&mut self, screen: &mut Screen) -> Result<(), std::io::Error> {
+        self.backend.draw(screen)?;
+        screen.present();
+        Ok(())
     }
 
-    pub fn cursor_show() {
-        stdout().execute(cursor::Show).ok();
+    pub fn cursor_hide(&mut self) {
+        self.backend.set_cursor_visible(false).ok();
     }
 
-    pub fn cursor_unblink() {
-        stdout().execute(cursor::DisableBlinking).ok();
+    pub fn cursor_show(&mut self) {
+        self.backend.set_cursor_visible(true).ok();
     }
 
-    pub fn cursor_blink() {
-        stdout().execute(cursor::EnableBlinking).ok();
+    pub fn cursor_unblink(&mut self) {
+        self.backend.set_cursor_blinking(false).ok();
+        self.backend.flush().ok();
     }
 
-    pub fn set_colors(colors: Colors) {
-        stdout().execute(SetColors(colors)).ok();
+    pub fn cursor_blink(&mut self) {
+        self.backend.set_cursor_blinking(true).ok();
+        self.backend.flush().ok();
     }
 
-    pub fn reset_colors() {
-        stdout().execute(ResetColor).ok();
+    pub fn reset_colors(&mut self) {
+        self.backend.reset_colors().ok();
+        self.backend.flush().ok();
     }
 }
@@ -0,0 +1,922 @@
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+use crossterm::event::{KeyCode, KeyModifiers};
+use hecto::{Config, Document, Editor, HeadlessBackend, Terminal};
+
+// runs the editor over `document` until the scripted keys quit it
+fn run(backend: &HeadlessBackend, document: Document) {
+    let terminal = Terminal::with_backend(Box::new(backend.clone()), true).unwrap();
+    Editor::new(terminal, document, Config::default()).run();
+}
+
+fn ctrl(backend: &HeadlessBackend, c: char) {
+    backend.push_key(KeyCode::Char(c), KeyModifiers::CONTROL);
+}
+
+fn key(backend: &HeadlessBackend, code: KeyCode) {
+    backend.push_key(code, KeyModifiers::NONE);
+}
+
+fn temp_file(name: &str, contents: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("hecto-test-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join(name);
+    fs::write(&path, contents).unwrap();
+    path
+}
+
+// a fresh directory `name` next to the files of `temp_file`
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("hecto-test-{}", std::process::id())).join(name);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn shows(frame: &[String], text: &str) -> bool {
+    frame.iter().any(|line| line.contains(text))
+}
+
+#[test]
+fn typing_shows_text() {
+    let backend = HeadlessBackend::new(40, 10);
+    backend.push_str("hello\nworld");
+    ctrl(&backend, 'q');
+    backend.push_str("y\n");
+    run(&backend, Document::default());
+
+    let frames = backend.frames();
+    let typed = frames.iter().find(|frame| shows(frame, "world")).unwrap();
+    assert!(typed[0].starts_with("1 hello"));
+    assert!(typed[1].starts_with("2 world"));
+    assert!(frames.iter().any(|frame| shows(frame, "Quit without saving? Y/N")));
+}
+
+#[test]
+fn ctrl_backspace_deletes_a_word() {
+    let backend = HeadlessBackend::new(40, 10);
+    backend.push_str("foo bar baz");
+    // the 0x08 most terminals send for Ctrl-Backspace, as crossterm reads it
+    ctrl(&backend, 'h');
+    backend.push_key(KeyCode::Backspace, KeyModifiers::ALT);
+    ctrl(&backend, 'q');
+    backend.push_str("y\n");
+    run(&backend, Document::default());
+
+    let last = backend.frames().into_iter().rev().find(|frame| shows(frame, "foo")).unwrap();
+    assert_eq!(last[0].trim_end(), "1 foo");
+}
+
+#[test]
+fn saving_writes_the_file() {
+    let path = temp_file("save.txt", "one\ntwo\n");
+    let backend = HeadlessBackend::new(40, 10);
+    key(&backend, KeyCode::End);
+    backend.push_str("!");
+    ctrl(&backend, 's');
+    ctrl(&backend, 'q');
+    run(&backend, Document::open(path.to_str().unwrap()).unwrap());
+
+    assert_eq!(fs::read_to_string(&path).unwrap(), "one!\ntwo\n");
+    assert!(backend.frames().iter().any(|frame| shows(frame, "File saved successfully")));
+    fs::remove_file(path).ok();
+}
+
+#[test]
+fn scrolling_moves_the_view() {
+    let contents: String = (1..=50).map(|n| format!("line {}\n", n)).collect();
+    let path = temp_file("scroll.txt", &contents);
+    let backend = HeadlessBackend::new(30, 10);
+    key(&backend, KeyCode::PageDown);
+    for _ in 0..20 {
+        key(&backend, KeyCode::Down);
+    }
+    ctrl(&backend, 'q');
+    run(&backend, Document::open(path.to_str().unwrap()).unwrap());
+
+    let frames = backend.frames();
+    let first = frames.iter().find(|frame| shows(frame, "line")).unwrap();
+    assert!(first[0].starts_with(" 1 line 1"));
+    let last = frames.iter().rev().find(|frame| shows(frame, "line")).unwrap();
+    assert!(!shows(last, "line 1 "));
+    assert!(shows(last, "line 28"));
+    fs::remove_file(path).ok();
+}
+
+#[test]
+fn quitting_restores_the_terminal() {
+    let backend = HeadlessBackend::new(40, 10);
+    ctrl(&backend, 'q');
+    run(&backend, Document::default());
+
+    assert!(!backend.is_raw_mode());
+    assert!(!backend.is_alternate_screen());
+    let last = backend.frames().pop().unwrap();
+    assert!(last.iter().all(|line| line.trim().is_empty()));
+}
+
+#[test]
+fn quitting_a_dirty_buffer_asks_first() {
+    let backend = HeadlessBackend::new(40, 10);
+    backend.push_str("x");
+    ctrl(&backend, 'q');
+    backend.push_str("n\n");
+    backend.push_str("y");
+    ctrl(&backend, 'q');
+    backend.push_str("y\n");
+    run(&backend, Document::default());
+
+    let frames = backend.frames();
+    let asked = frames.iter().filter(|frame| shows(frame, "Quit without saving?")).count();
+    assert!(asked >= 2);
+    assert!(frames.iter().any(|frame| frame[0].starts_with("1 xy")));
+    assert!(!backend.is_raw_mode());
+}
+
+#[test]
+fn swap_file_is_recovered_and_removed_on_save() {
+    let path = temp_file("recover.txt", "one\ntwo\n");
+    let swap = path.with_file_name(".recover.txt.swp");
+    fs::write(&swap, "hecto swap 3 1\none\ntwo!\nthree\n").unwrap();
+    let backend = HeadlessBackend::new(60, 10);
+    backend.push_str("r\n");
+    backend.push_str("?");
+    ctrl(&backend, 's');
+    ctrl(&backend, 'q');
+    run(&backend, Document::open(path.to_str().unwrap()).unwrap());
+
+    assert!(backend.frames().iter().any(|frame| shows(frame, "Swap file found")));
+    assert_eq!(fs::read_to_string(&path).unwrap(), "one\ntwo?!\nthree\n");
+    assert!(!swap.exists());
+    fs::remove_file(path).ok();
+}
+
+#[test]
+fn swap_file_can_be_diffed_and_discarded() {
+    let path = temp_file("discard.txt", "one\ntwo\n");
+    let swap = path.with_file_name(".discard.txt.swp");
+    fs::write(&swap, "hecto swap 0 0\none\nthree\n").unwrap();
+    let backend = HeadlessBackend::new(60, 10);
+    backend.push_str("d\n");
+    key(&backend, KeyCode::Esc);
+    backend.push_str("x\n");
+    ctrl(&backend, 'q');
+    run(&backend, Document::open(path.to_str().unwrap()).unwrap());
+
+    let frames = backend.frames();
+    assert!(frames.iter().any(|frame| shows(frame, "+three") && shows(frame, "-two")));
+    assert!(frames.iter().any(|frame| shows(frame, "Swap file discarded")));
+    assert_eq!(fs::read_to_string(&path).unwrap(), "one\ntwo\n");
+    assert!(!swap.exists());
+    fs::remove_file(path).ok();
+}
+
+#[test]
+fn panics_restore_the_terminal_and_keep_unsaved_edits() {
+    let path = temp_file("panic.txt", "one\n");
+    let swap = path.with_file_name(".panic.txt.swp");
+    let backend = HeadlessBackend::new(40, 10);
+    // the script runs out without quitting, so the next read fails
+    backend.push_str("lost ");
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        run(&backend, Document::open(path.to_str().unwrap()).unwrap());
+    }));
+
+    assert!(result.is_err());
+    assert!(!backend.is_raw_mode());
+    let swapped = fs::read_to_string(&swap).unwrap();
+    assert!(swapped.starts_with(&format!("hecto swap 5 0 {} ", std::process::id())));
+    assert!(swapped.ends_with("\nlost one\n"));
+    fs::remove_file(swap).ok();
+    fs::remove_file(path).ok();
+}
+
+#[test]
+fn swap_files_of_running_editors_are_left_alone() {
+    let path = temp_file("running.txt", "one\n");
+    let swap = path.with_file_name(".running.txt.swp");
+    let backend = HeadlessBackend::new(60, 10);
+    // the first editor leaves its swap file behind, owned by this process
+    backend.push_str("mine ");
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        run(&backend, Document::open(path.to_str().unwrap()).unwrap());
+    }));
+    assert!(result.is_err());
+    let swapped = fs::read_to_string(&swap).unwrap();
+
+    let backend = HeadlessBackend::new(60, 10);
+    backend.push_str("x");
+    ctrl(&backend, 'q');
+    run(&backend, Document::open(path.to_str().unwrap()).unwrap());
+
+    let frames = backend.frames();
+    assert!(frames.iter().any(|frame| shows(frame, "Also being edited by process")));
+    assert!(!frames.iter().any(|frame| shows(frame, "Swap file found")));
+    assert_eq!(fs::read_to_string(&swap).unwrap(), swapped);
+    assert_eq!(fs::read_to_string(&path).unwrap(), "one\n");
+    fs::remove_file(swap).ok();
+    fs::remove_file(path).ok();
+}
+
+#[test]
+fn suspending_hands_back_the_terminal_and_redraws() {
+    let backend = HeadlessBackend::new(40, 10);
+    backend.push_str("a");
+    ctrl(&backend, 'z');
+    backend.push_str("b");
+    ctrl(&backend, 'q');
+    backend.push_str("y\n");
+    run(&backend, Document::default());
+
+    assert_eq!(backend.suspensions(), 1);
+    assert!(backend.frames().iter().any(|frame| frame[0].starts_with("1 ab")));
+    assert!(!backend.is_raw_mode());
+}
+
+#[test]
+fn external_changes_are_reported_and_reloaded() {
+    let path = temp_file("external.txt", "one\ntwo\n");
+    let document = Document::open(path.to_str().unwrap()).unwrap();
+    fs::write(&path, "one\ntwo\nthree\n").unwrap();
+    let backend = HeadlessBackend::new(120, 10);
+    key(&backend, KeyCode::Down);
+    key(&backend, KeyCode::End);
+    ctrl(&backend, 'r');
+    backend.push_str("!");
+    ctrl(&backend, 's');
+    ctrl(&backend, 'q');
+    run(&backend, document);
+
+    let frames = backend.frames();
+    assert!(frames.iter().any(|frame| shows(frame, "changed on disk, Ctrl-R to reload")));
+    assert_eq!(fs::read_to_string(&path).unwrap(), "one\ntwo!\nthree\n");
+    fs::remove_file(path).ok();
+}
+
+#[test]
+fn external_changes_are_noticed_while_idle() {
+    let path = temp_file("idle.txt", "one\n");
+    let document = Document::open(path.to_str().unwrap()).unwrap();
+    fs::write(&path, "one\ntwo\n").unwrap();
+    let backend = HeadlessBackend::new(120, 10);
+    backend.push_pause(Duration::from_millis(2500));
+    ctrl(&backend, 'q');
+    run(&backend, document);
+
+    // nothing was typed, so only the disk check timer can have noticed
+    let frames = backend.frames();
+    let idle = &frames[..frames.len() - 1];
+    assert!(idle.iter().any(|frame| shows(frame, "changed on disk, Ctrl-R to reload")));
+    fs::remove_file(path).ok();
+}
+
+#[test]
+fn saving_over_a_newer_file_asks_first() {
+    let path = temp_file("newer.txt", "one\n");
+    let document = Document::open(path.to_str().unwrap()).unwrap();
+    fs::write(&path, "theirs\n").unwrap();
+    let backend = HeadlessBackend::new(60, 10);
+    backend.push_str("x");
+    ctrl(&backend, 's');
+    backend.push_str("n\n");
+    ctrl(&backend, 'q');
+    backend.push_str("y\n");
+    run(&backend, document);
+
+    let frames = backend.frames();
+    assert!(frames.iter().any(|frame| shows(frame, "Overwrite? Y/N")));
+    assert!(frames.iter().any(|frame| shows(frame, "Save cancelled")));
+    assert_eq!(fs::read_to_string(&path).unwrap(), "theirs\n");
+    fs::remove_file(path).ok();
+}
+
+#[test]
+fn buffers_are_switched_in_order() {
+    let first = temp_file("first.txt", "first\n");
+    let second = temp_file("second.txt", "second\n");
+    let backend = HeadlessBackend::new(60, 10);
+    backend.push_key(KeyCode::Char('n'), KeyModifiers::ALT);
+    backend.push_str("2");
+    backend.push_key(KeyCode::Char('n'), KeyModifiers::ALT);
+    backend.push_str("1");
+    ctrl(&backend, 's');
+    backend.push_key(KeyCode::Char('p'), KeyModifiers::ALT);
+    ctrl(&backend, 's');
+    ctrl(&backend, 'q');
+    let terminal = Terminal::with_backend(Box::new(backend.clone()), true).unwrap();
+    let mut editor = Editor::new(terminal, Document::open(first.to_str().unwrap()).unwrap(), Config::default());
+    editor.add_buffer(Document::open(second.to_str().unwrap()).unwrap());
+    editor.run();
+
+    assert!(backend.frames().iter().any(|frame| shows(frame, "[2/2]")));
+    assert_eq!(fs::read_to_string(&first).unwrap(), "1first\n");
+    assert_eq!(fs::read_to_string(&second).unwrap(), "2second\n");
+    fs::remove_file(first).ok();
+    fs::remove_file(second).ok();
+}
+
+#[test]
+fn cursor_keeps_its_column_and_jumps_across_the_document() {
+    let path = temp_file("navigate.txt", "abcdefgh\nab\nabcdefgh\nf(x, [y])\n");
+    let backend = HeadlessBackend::new(40, 10);
+    for _ in 0..5 {
+        key(&backend, KeyCode::Right);
+    }
+    // the short row in between does not pull the column back
+    key(&backend, KeyCode::Down);
+    key(&backend, KeyCode::Down);
+    backend.push_str("!");
+    key(&backend, KeyCode::Down);
+    key(&backend, KeyCode::Home);
+    key(&backend, KeyCode::Right);
+    backend.push_key(KeyCode::Char(']'), KeyModifiers::ALT);
+    backend.push_str("?");
+    backend.push_key(KeyCode::End, KeyModifiers::CONTROL);
+    backend.push_str("end");
+    backend.push_key(KeyCode::Home, KeyModifiers::CONTROL);
+    backend.push_str("^");
+    ctrl(&backend, 's');
+    ctrl(&backend, 'q');
+    run(&backend, Document::open(path.to_str().unwrap()).unwrap());
+
+    assert_eq!(
+        fs::read_to_string(&path).unwrap(),
+        "^abcdefgh\nab\nabcde!fgh\nf(x, [y]?)end\n"
+    );
+    fs::remove_file(path).ok();
+}
+
+#[test]
+fn indenting_and_new_lines_are_undone_in_one_step() {
+    let path = temp_file("undo.txt", "  one\ntwo\n");
+    let backend = HeadlessBackend::new(40, 10);
+    backend.push_key(KeyCode::Down, KeyModifiers::SHIFT);
+    backend.push_key(KeyCode::End, KeyModifiers::SHIFT);
+    backend.push_key(KeyCode::Char('}'), KeyModifiers::ALT);
+    backend.push_key(KeyCode::Char('u'), KeyModifiers::ALT);
+    key(&backend, KeyCode::Up);
+    key(&backend, KeyCode::End);
+    key(&backend, KeyCode::Enter);
+    backend.push_key(KeyCode::Char('u'), KeyModifiers::ALT);
+    // back at the saved text, so quitting doesn't ask
+    ctrl(&backend, 'q');
+    run(&backend, Document::open(path.to_str().unwrap()).unwrap());
+
+    let frames = backend.frames();
+    assert!(frames.iter().any(|frame| frame[0].starts_with("1       one") && frame[1].starts_with("2     two")));
+    assert!(frames.iter().any(|frame| frame[1].starts_with("2   ") && frame[2].starts_with("3 two")));
+    let last = frames.iter().rev().find(|frame| shows(frame, "one")).unwrap();
+    assert!(last[0].starts_with("1   one"));
+    assert!(last[1].starts_with("2 two"));
+    assert!(!shows(last, "(modified)"));
+    fs::remove_file(path).ok();
+}
+
+#[test]
+fn read_only_buffers_block_edits_until_allowed() {
+    let path = temp_file("readonly.txt", "text\n");
+    let mut document = Document::open(path.to_str().unwrap()).unwrap();
+    document.set_read_only(true);
+    let backend = HeadlessBackend::new(80, 10);
+    backend.push_str("x");
+    ctrl(&backend, 'k');
+    backend.push_key(KeyCode::Char('d'), KeyModifiers::ALT);
+    backend.push_key(KeyCode::Char('b'), KeyModifiers::ALT);
+    key(&backend, KeyCode::Right);
+    key(&backend, KeyCode::Delete);
+    key(&backend, KeyCode::Esc);
+    key(&backend, KeyCode::Home);
+    backend.push_key(KeyCode::Char('r'), KeyModifiers::ALT);
+    backend.push_str("y");
+    ctrl(&backend, 's');
+    ctrl(&backend, 'q');
+    run(&backend, document);
+
+    let frames = backend.frames();
+    assert!(frames.iter().any(|frame| shows(frame, "[RO]") && shows(frame, "Alt-R to allow edits")));
+    assert_eq!(fs::read_to_string(&path).unwrap(), "ytext\n");
+    fs::remove_file(path).ok();
+}
+
+#[test]
+fn read_only_buffers_can_be_saved_elsewhere() {
+    let path = temp_file("original.txt", "text\n");
+    let copy = path.with_file_name("copy.txt");
+    let mut document = Document::open(path.to_str().unwrap()).unwrap();
+    document.set_read_only(true);
+    let backend = HeadlessBackend::new(80, 10);
+    ctrl(&backend, 's');
+    backend.push_str("a\n");
+    backend.push_str(&format!("{}\n", copy.display()));
+    // the copy is not read-only, so it can be edited and saved straight away
+    backend.push_str("z");
+    ctrl(&backend, 's');
+    ctrl(&backend, 'q');
+    run(&backend, document);
+
+    let frames = backend.frames();
+    assert!(frames.iter().any(|frame| shows(frame, "S = save with sudo, A = save as")));
+    assert!(!shows(frames.last().unwrap(), "[RO]"));
+    assert_eq!(fs::read_to_string(&copy).unwrap(), "ztext\n");
+    assert_eq!(fs::read_to_string(&path).unwrap(), "text\n");
+    fs::remove_file(copy).ok();
+    fs::remove_file(path).ok();
+}
+
+#[test]
+fn regex_replace_expands_capture_groups() {
+    let path = temp_file("replace.rs", "let a = foo(1);\nlet b = foo(22);\nfoo();\n");
+    let backend = HeadlessBackend::new(80, 10);
+    ctrl(&backend, 't');
+    backend.push_key(KeyCode::Char('r'), KeyModifiers::ALT);
+    backend.push_str("foo\\((\\d+)\\)\nbar($1)\n");
+    backend.push_str("n");
+    backend.push_str("a");
+    ctrl(&backend, 's');
+    ctrl(&backend, 'q');
+    run(&backend, Document::open(path.to_str().unwrap()).unwrap());
+
+    assert_eq!(fs::read_to_string(&path).unwrap(), "let a = foo(1);\nlet b = bar(22);\nfoo();\n");
+    assert!(backend.frames().iter().any(|frame| shows(frame, "Replace (regex): ")));
+    assert!(backend.frames().iter().any(|frame| shows(frame, "Replaced 1 occurrence")));
+    fs::remove_file(path).ok();
+}
+
+#[test]
+fn search_toggles_case_and_whole_word() {
+    let path = temp_file("search.txt", "food\nfood Foo\n");
+    let backend = HeadlessBackend::new(80, 10);
+    ctrl(&backend, 'f');
+    backend.push_key(KeyCode::Char('i'), KeyModifiers::ALT);
+    backend.push_key(KeyCode::Char('w'), KeyModifiers::ALT);
+    backend.push_str("foo\n!");
+    ctrl(&backend, 's');
+    ctrl(&backend, 'q');
+    run(&backend, Document::open(path.to_str().unwrap()).unwrap());
+
+    assert_eq!(fs::read_to_string(&path).unwrap(), "food\nfood Foo!\n");
+    fs::remove_file(path).ok();
+}
+
+#[test]
+fn invalid_patterns_are_reported() {
+    let backend = HeadlessBackend::new(80, 10);
+    ctrl(&backend, 'f');
+    backend.push_key(KeyCode::Char('r'), KeyModifiers::ALT);
+    backend.push_str("(\n");
+    ctrl(&backend, 'q');
+    run(&backend, Document::from_text("text"));
+
+    assert!(backend.frames().iter().any(|frame| shows(frame, "Invalid pattern: unclosed group")));
+}
+
+#[test]
+fn patterns_match_across_rows() {
+    let path = temp_file("blank.txt", "a\n\n\nb\n\nc\n\n\n\nd\n");
+    let backend = HeadlessBackend::new(80, 10);
+    ctrl(&backend, 't');
+    backend.push_key(KeyCode::Char('r'), KeyModifiers::ALT);
+    backend.push_str("\\n\\n\\n+\n\\n\\n\na");
+    backend.push_key(KeyCode::Char('u'), KeyModifiers::ALT);
+    backend.push_key(KeyCode::Char('e'), KeyModifiers::ALT);
+    ctrl(&backend, 's');
+    ctrl(&backend, 'q');
+    run(&backend, Document::open(path.to_str().unwrap()).unwrap());
+
+    assert_eq!(fs::read_to_string(&path).unwrap(), "a\n\nb\n\nc\n\nd\n");
+    assert!(backend.frames().iter().any(|frame| shows(frame, "Replaced 2 occurrences")));
+    fs::remove_file(path).ok();
+}
+
+#[test]
+fn replace_within_the_selection() {
+    let path = temp_file("scope.txt", "x x\nx x\nx x\n");
+    let backend = HeadlessBackend::new(80, 10);
+    key(&backend, KeyCode::Down);
+    backend.push_key(KeyCode::Down, KeyModifiers::SHIFT);
+    ctrl(&backend, 't');
+    backend.push_key(KeyCode::Char('s'), KeyModifiers::ALT);
+    backend.push_str("x\ny\ny");
+    backend.push_str("a");
+    ctrl(&backend, 's');
+    ctrl(&backend, 'q');
+    run(&backend, Document::open(path.to_str().unwrap()).unwrap());
+
+    assert_eq!(fs::read_to_string(&path).unwrap(), "x x\ny y\nx x\n");
+    assert!(backend.frames().iter().any(|frame| shows(frame, "Replace (in selection): ")));
+    fs::remove_file(path).ok();
+}
+
+#[test]
+fn replace_within_the_selection_with_longer_text() {
+    let path = temp_file("scope-longer.txt", "x x\nx x x\nx x\n");
+    let backend = HeadlessBackend::new(80, 10);
+    key(&backend, KeyCode::Down);
+    backend.push_key(KeyCode::Down, KeyModifiers::SHIFT);
+    ctrl(&backend, 't');
+    backend.push_key(KeyCode::Char('s'), KeyModifiers::ALT);
+    backend.push_str("x\nlonger\nyyy");
+    ctrl(&backend, 's');
+    ctrl(&backend, 'q');
+    run(&backend, Document::open(path.to_str().unwrap()).unwrap());
+
+    assert_eq!(fs::read_to_string(&path).unwrap(), "x x\nlonger longer longer\nx x\n");
+    assert!(backend.frames().iter().any(|frame| shows(frame, "Replaced 3 occurrences")));
+    fs::remove_file(path).ok();
+}
+
+#[test]
+fn grep_lists_matches_and_opens_them() {
+    let dir = temp_dir("grep");
+    fs::create_dir_all(dir.join(".git")).unwrap();
+    fs::create_dir_all(dir.join("sub")).unwrap();
+    temp_file("grep/.gitignore", "ignored.txt\n");
+    temp_file("grep/ignored.txt", "needle\n");
+    let first = temp_file("grep/a.txt", "alpha\nneedle here\n");
+    let second = temp_file("grep/sub/b.txt", "needle\n");
+    let backend = HeadlessBackend::new(120, 10);
+    ctrl(&backend, 'g');
+    backend.push_str("needle\n");
+    // the search runs in the background while nothing is typed
+    backend.push_pause(Duration::from_millis(500));
+    key(&backend, KeyCode::Down);
+    backend.push_str("\nX");
+    ctrl(&backend, 's');
+    ctrl(&backend, 'q');
+    run(&backend, Document::open(first.to_str().unwrap()).unwrap());
+
+    let frames = backend.frames();
+    assert!(frames.iter().any(|frame| shows(frame, "for needle: ") && shows(frame, "files, Esc cancels")));
+    let results = frames.iter().find(|frame| shows(frame, "2 matches for needle")).unwrap();
+    assert!(shows(results, "a.txt:2: needle here"));
+    assert!(shows(results, "b.txt:1: needle"));
+    assert!(!shows(results, "ignored.txt"));
+    assert!(shows(results, "[grep: needle]"));
+    assert_eq!(fs::read_to_string(&second).unwrap(), "Xneedle\n");
+    fs::remove_dir_all(dir).ok();
+}
+
+#[test]
+fn grep_is_cancelled_with_esc() {
+    let dir = temp_dir("grep_cancel");
+    let path = temp_file("grep_cancel/a.txt", "needle\n");
+    let backend = HeadlessBackend::new(120, 10);
+    ctrl(&backend, 'g');
+    backend.push_key(KeyCode::Char('s'), KeyModifiers::ALT);
+    backend.push_str("needle\n");
+    key(&backend, KeyCode::Esc);
+    backend.push_pause(Duration::from_millis(300));
+    ctrl(&backend, 'q');
+    run(&backend, Document::open(path.to_str().unwrap()).unwrap());
+
+    let frames = backend.frames();
+    assert!(frames.iter().any(|frame| shows(frame, "Grep: ")));
+    assert!(!frames.iter().any(|frame| shows(frame, "in selection")));
+    assert!(frames.iter().any(|frame| shows(frame, "Grep cancelled")));
+    assert!(!frames.iter().any(|frame| shows(frame, "[grep: needle]")));
+    fs::remove_dir_all(dir).ok();
+}
+
+#[test]
+fn file_finder_ranks_previews_and_opens() {
+    let dir = temp_dir("finder");
+    fs::create_dir_all(dir.join(".git")).unwrap();
+    fs::create_dir_all(dir.join("src")).unwrap();
+    let main = temp_file("finder/src/main.rs", "fn main() {}\n");
+    let lib = temp_file("finder/src/lib.rs", "pub mod library;\n");
+    temp_file("finder/README.md", "readme\n");
+    let backend = HeadlessBackend::new(80, 10);
+    ctrl(&backend, 'o');
+    backend.push_str("slib");
+    backend.push_str("\nX");
+    ctrl(&backend, 's');
+    ctrl(&backend, 'q');
+    run(&backend, Document::open(main.to_str().unwrap()).unwrap());
+
+    let frames = backend.frames();
+    let picker = frames.iter().find(|frame| shows(frame, "Open [1/3]: slib")).unwrap();
+    assert!(picker[0].starts_with("src/lib.rs"));
+    assert!(shows(picker, "\u{2502} pub mod library;"));
+    assert!(frames.iter().any(|frame| shows(frame, "Open [3/3]: ") && shows(frame, "README.md")));
+    assert_eq!(fs::read_to_string(&lib).unwrap(), "Xpub mod library;\n");
+    fs::remove_dir_all(dir).ok();
+}
+
+#[test]
+fn directories_are_listed_and_edited() {
+    let dir = temp_dir("browse");
+    fs::create_dir_all(dir.join("sub")).unwrap();
+    temp_file("browse/b.txt", "bee\n");
+    temp_file("browse/A.txt", "ay\n");
+    temp_file("browse/sub/inner.txt", "inner\n");
+    let backend = HeadlessBackend::new(80, 12);
+    key(&backend, KeyCode::Down); // ../ is on row 1, sub/ on row 2
+    backend.push_str("\n");
+    key(&backend, KeyCode::Down);
+    backend.push_str("\nX");
+    ctrl(&backend, 's');
+    backend.push_key(KeyCode::Char('n'), KeyModifiers::ALT);
+    key(&backend, KeyCode::Backspace);
+    backend.push_str("nnew/made.txt\n");
+    key(&backend, KeyCode::Down);
+    key(&backend, KeyCode::Down);
+    backend.push_str("rc.txt\n");
+    key(&backend, KeyCode::Up);
+    backend.push_str("dy\n");
+    ctrl(&backend, 'q');
+    let terminal = Terminal::with_backend(Box::new(backend.clone()), true).unwrap();
+    let mut editor = Editor::new(terminal, Document::default(), Config::default());
+    editor.open_directory(&dir);
+    editor.run();
+
+    let frames = backend.frames();
+    let listing = frames.iter().find(|frame| shows(frame, "[dir: ")).unwrap();
+    let rows: Vec<&str> = listing.iter().skip(1).take(4).map(|row| row.trim_end()).collect();
+    assert_eq!(rows, vec!["2 ../", "3 sub/", "4 A.txt", "5 b.txt"]);
+    assert_eq!(fs::read_to_string(dir.join("sub/inner.txt")).unwrap(), "Xinner\n");
+    assert!(dir.join("new/made.txt").is_file());
+    assert!(frames.iter().any(|frame| shows(frame, "Renamed A.txt to c.txt")));
+    assert!(!dir.join("A.txt").exists());
+    assert!(dir.join("c.txt").exists());
+    assert!(!dir.join("b.txt").exists());
+    fs::remove_dir_all(dir).ok();
+}
+
+#[test]
+fn files_opened_later_are_checked_for_swap_files() {
+    let dir = temp_dir("swap-later");
+    let path = temp_file("swap-later/second.txt", "one\n");
+    let swap = path.with_file_name(".second.txt.swp");
+    fs::write(&swap, "hecto swap 0 0\nrecovered\n").unwrap();
+    let backend = HeadlessBackend::new(80, 12);
+    key(&backend, KeyCode::Down); // ../ is on row 1, the swap file on row 2
+    key(&backend, KeyCode::Down);
+    backend.push_str("\n");
+    backend.push_str("r\n");
+    ctrl(&backend, 's');
+    ctrl(&backend, 'q');
+    let terminal = Terminal::with_backend(Box::new(backend.clone()), true).unwrap();
+    let mut editor = Editor::new(terminal, Document::default(), Config::default());
+    editor.open_directory(&dir);
+    editor.run();
+
+    assert!(backend.frames().iter().any(|frame| shows(frame, "Swap file found")));
+    assert_eq!(fs::read_to_string(&path).unwrap(), "recovered\n");
+    assert!(!swap.exists());
+    fs::remove_dir_all(dir).ok();
+}
+
+#[test]
+fn open_buffers_follow_renamed_and_deleted_files() {
+    let dir = temp_dir("follow");
+    temp_file("follow/a.txt", "a\n");
+    temp_file("follow/b.txt", "b\n");
+    let backend = HeadlessBackend::new(80, 12);
+    key(&backend, KeyCode::Down); // ../ is on row 1, a.txt on row 2
+    backend.push_str("\n");
+    backend.push_key(KeyCode::Char('n'), KeyModifiers::ALT);
+    key(&backend, KeyCode::Down);
+    backend.push_str("\n");
+    backend.push_key(KeyCode::Char('n'), KeyModifiers::ALT);
+    backend.push_key(KeyCode::Char('n'), KeyModifiers::ALT);
+    backend.push_key(KeyCode::Char('r'), KeyModifiers::ALT);
+    backend.push_str("dy\n");
+    backend.push_str("rc.txt\n");
+    backend.push_key(KeyCode::Char('n'), KeyModifiers::ALT);
+    backend.push_key(KeyCode::Char('n'), KeyModifiers::ALT);
+    backend.push_str("Z");
+    ctrl(&backend, 's');
+    ctrl(&backend, 'q');
+    backend.push_str("y\n");
+    let terminal = Terminal::with_backend(Box::new(backend.clone()), true).unwrap();
+    let mut editor = Editor::new(terminal, Document::default(), Config::default());
+    editor.open_directory(&dir);
+    editor.run();
+
+    let frames = backend.frames();
+    assert!(frames.iter().any(|frame| shows(frame, "Listings are always read-only")));
+    assert!(frames.iter().any(|frame| shows(frame, "Deleted b.txt, 1 open buffer now unsaved")));
+    assert!(frames.iter().any(|frame| shows(frame, "Renamed a.txt to c.txt, 1 open buffer renamed along")));
+    assert!(frames.iter().any(|frame| shows(frame, "[2/3]") && shows(frame, "(modified)")));
+    assert_eq!(fs::read_to_string(dir.join("c.txt")).unwrap(), "Za\n");
+    assert!(!dir.join("a.txt").exists());
+    assert!(!dir.join("b.txt").exists());
+    fs::remove_dir_all(dir).ok();
+}
+
+#[test]
+fn cursors_rename_occurrences_as_one_step() {
+    let path = temp_file("cursors.txt", "foo = 1\nfoo = 2\nbar(foo)\n");
+    let backend = HeadlessBackend::new(60, 10);
+    ctrl(&backend, 'd');
+    ctrl(&backend, 'd');
+    ctrl(&backend, 'd');
+    for _ in 0..3 {
+        key(&backend, KeyCode::Backspace);
+    }
+    backend.push_str("baz");
+    ctrl(&backend, 's');
+    backend.push_key(KeyCode::Char('u'), KeyModifiers::ALT);
+    ctrl(&backend, 'q');
+    backend.push_str("y\n");
+    run(&backend, Document::open(path.to_str().unwrap()).unwrap());
+
+    let frames = backend.frames();
+    assert!(frames.iter().any(|frame| shows(frame, "3 cursors")));
+    assert_eq!(fs::read_to_string(&path).unwrap(), "baz = 1\nbaz = 2\nbar(baz)\n");
+    let undone = frames.iter().rev().find(|frame| shows(frame, "Quit without saving")).unwrap();
+    assert!(shows(undone, "foo = 1") && shows(undone, "foo = 2") && shows(undone, "bar(foo)"));
+    fs::remove_file(path).ok();
+}
+
+#[test]
+fn cursors_added_by_row_and_column() {
+    let path = temp_file("column.txt", "a\nbb\nc\nd\n");
+    let backend = HeadlessBackend::new(60, 10);
+    backend.push_key(KeyCode::Down, KeyModifiers::ALT | KeyModifiers::SHIFT);
+    backend.push_str("- ");
+    key(&backend, KeyCode::End);
+    backend.push_str(";");
+    key(&backend, KeyCode::Esc);
+    key(&backend, KeyCode::Down);
+    backend.push_key(KeyCode::Down, KeyModifiers::SHIFT);
+    backend.push_key(KeyCode::Char('c'), KeyModifiers::ALT);
+    key(&backend, KeyCode::Enter);
+    ctrl(&backend, 's');
+    ctrl(&backend, 'q');
+    run(&backend, Document::open(path.to_str().unwrap()).unwrap());
+
+    assert_eq!(fs::read_to_string(&path).unwrap(), "- a;\n- bb;\nc\n\nd\n\n");
+    fs::remove_file(path).ok();
+}
+
+#[test]
+fn blocks_are_cut_and_pasted_as_rectangles() {
+    let path = temp_file("block.txt", "id name\n1 ab\n22 cde\n3\n");
+    let backend = HeadlessBackend::new(60, 10);
+    backend.push_key(KeyCode::Char('b'), KeyModifiers::ALT);
+    backend.push_key(KeyCode::Right, KeyModifiers::SHIFT);
+    backend.push_key(KeyCode::Right, KeyModifiers::SHIFT);
+    backend.push_key(KeyCode::Down, KeyModifiers::SHIFT);
+    backend.push_key(KeyCode::Down, KeyModifiers::SHIFT);
+    ctrl(&backend, 'x');
+    key(&backend, KeyCode::Down);
+    key(&backend, KeyCode::End);
+    ctrl(&backend, 'v');
+    ctrl(&backend, 's');
+    ctrl(&backend, 'q');
+    run(&backend, Document::open(path.to_str().unwrap()).unwrap());
+
+    assert!(backend.frames().iter().any(|frame| shows(frame, "Cut 3 row(s) of a block")));
+    assert_eq!(fs::read_to_string(&path).unwrap(), " name\nab\n cde\n3id\n 1 \n 22\n");
+    fs::remove_file(path).ok();
+}
+
+#[test]
+fn typing_in_a_block_pads_short_rows() {
+    let path = temp_file("block_insert.txt", "aaaa\nb\ncccc\n");
+    let backend = HeadlessBackend::new(60, 10);
+    for _ in 0..3 {
+        key(&backend, KeyCode::Right);
+    }
+    backend.push_key(KeyCode::Char('b'), KeyModifiers::ALT);
+    backend.push_key(KeyCode::Down, KeyModifiers::SHIFT);
+    backend.push_key(KeyCode::Down, KeyModifiers::SHIFT);
+    backend.push_str("|x");
+    // the padding goes with the first key typed, which is one undo step
+    backend.push_key(KeyCode::Char('u'), KeyModifiers::ALT);
+    backend.push_key(KeyCode::Char('u'), KeyModifiers::ALT);
+    ctrl(&backend, 's');
+    ctrl(&backend, 'q');
+    run(&backend, Document::open(path.to_str().unwrap()).unwrap());
+
+    assert!(backend.frames().iter().any(|frame| frame[1].starts_with("2 b  |x")));
+    assert_eq!(fs::read_to_string(&path).unwrap(), "aaaa\nb\ncccc\n");
+    fs::remove_file(path).ok();
+}
+
+#[test]
+fn blocks_are_measured_in_screen_columns() {
+    let path = temp_file("block_wide.txt", "日本語\nabcdef\n");
+    let backend = HeadlessBackend::new(60, 10);
+    key(&backend, KeyCode::Down);
+    key(&backend, KeyCode::Right);
+    key(&backend, KeyCode::Right);
+    backend.push_key(KeyCode::Char('b'), KeyModifiers::ALT);
+    backend.push_key(KeyCode::Up, KeyModifiers::SHIFT);
+    ctrl(&backend, 'x');
+    ctrl(&backend, 's');
+    ctrl(&backend, 'q');
+    run(&backend, Document::open(path.to_str().unwrap()).unwrap());
+
+    assert_eq!(fs::read_to_string(&path).unwrap(), "日語\nabef\n");
+    fs::remove_file(path).ok();
+}
+
+#[test]
+fn selections_are_copied_and_pasted() {
+    let path = temp_file("clipboard.txt", "hello\nworld\n");
+    let backend = HeadlessBackend::new(60, 10);
+    key(&backend, KeyCode::Right);
+    backend.push_key(KeyCode::Down, KeyModifiers::SHIFT);
+    ctrl(&backend, 'c');
+    key(&backend, KeyCode::End);
+    ctrl(&backend, 'v');
+    backend.push_str("!");
+    ctrl(&backend, 's');
+    ctrl(&backend, 'q');
+    run(&backend, Document::open(path.to_str().unwrap()).unwrap());
+
+    assert_eq!(fs::read_to_string(&path).unwrap(), "hello\nworldello\nw!\n");
+    fs::remove_file(path).ok();
+}
+
+#[test]
+fn macros_play_a_number_of_times_or_to_the_end() {
+    let path = temp_file("macro.txt", "a\nb\nc\nd\ne\n");
+    let backend = HeadlessBackend::new(80, 10);
+    backend.push_key(KeyCode::Char('m'), KeyModifiers::ALT);
+    key(&backend, KeyCode::Home);
+    backend.push_str("- ");
+    key(&backend, KeyCode::Down);
+    backend.push_key(KeyCode::Char('m'), KeyModifiers::ALT);
+    backend.push_key(KeyCode::Char('l'), KeyModifiers::ALT);
+    backend.push_str("2\n");
+    // keys pressed while a macro plays are dropped, so the script waits
+    backend.push_pause(Duration::from_millis(1));
+    backend.push_key(KeyCode::Char('l'), KeyModifiers::ALT);
+    backend.push_str("\n");
+    backend.push_pause(Duration::from_millis(1));
+    ctrl(&backend, 's');
+    ctrl(&backend, 'q');
+    run(&backend, Document::open(path.to_str().unwrap()).unwrap());
+
+    let frames = backend.frames();
+    assert!(frames.iter().any(|frame| shows(frame, "[REC]")));
+    assert!(frames.iter().any(|frame| shows(frame, "Recorded 4 keys")));
+    assert!(frames.iter().any(|frame| shows(frame, "Played the macro 2 time(s)")));
+    assert!(frames.iter().any(|frame| {
+        shows(frame, "Played the macro 2 time(s), stopped as the end of the file was reached")
+    }));
+    assert_eq!(fs::read_to_string(&path).unwrap(), "- a\n- b\n- c\n- d\n- e\n");
+    fs::remove_file(path).ok();
+}
+
+#[test]
+fn saved_macros_stop_at_the_first_failing_search() {
+    let config = temp_dir("config");
+    std::env::set_var("XDG_CONFIG_HOME", &config);
+    let path = temp_file("macro_search.txt", "x=1\nfoo\nx=2\nx=3\n");
+    let backend = HeadlessBackend::new(80, 10);
+    backend.push_key(KeyCode::Char('m'), KeyModifiers::ALT);
+    ctrl(&backend, 'f');
+    backend.push_str("x=\n");
+    key(&backend, KeyCode::Backspace);
+    key(&backend, KeyCode::Backspace);
+    backend.push_str("y:");
+    backend.push_key(KeyCode::Char('m'), KeyModifiers::ALT);
+    backend.push_key(KeyCode::Char('k'), KeyModifiers::ALT);
+    backend.push_str("rename\n");
+    backend.push_key(KeyCode::Char('o'), KeyModifiers::ALT);
+    backend.push_str("rename\n");
+    backend.push_key(KeyCode::Char('l'), KeyModifiers::ALT);
+    backend.push_str("\n");
+    backend.push_pause(Duration::from_millis(1));
+    ctrl(&backend, 's');
+    ctrl(&backend, 'q');
+    run(&backend, Document::open(path.to_str().unwrap()).unwrap());
+
+    let saved = fs::read_to_string(config.join("hecto/macros/rename")).unwrap();
+    assert_eq!(saved, "C-f\nx\n=\nEnter\nBackspace\nBackspace\ny\n:\n");
+    assert!(backend.frames().iter().any(|frame| shows(frame, "Loaded macro rename (8 keys)")));
+    assert!(backend.frames().iter().any(|frame| {
+        shows(frame, "Played the macro 3 time(s), stopped as a search failed")
+    }));
+    assert_eq!(fs::read_to_string(&path).unwrap(), "y:1\nfoo\ny:2\ny:3\n");
+    fs::remove_dir_all(config).ok();
+    fs::remove_file(path).ok();
+}
+
+#[test]
+fn esc_interrupts_a_playing_macro() {
+    let path = temp_file("macro_esc.txt", "a\nb\nc\nd\n");
+    let backend = HeadlessBackend::new(80, 10);
+    backend.push_key(KeyCode::Char('m'), KeyModifiers::ALT);
+    key(&backend, KeyCode::End);
+    backend.push_str("!");
+    key(&backend, KeyCode::Down);
+    backend.push_key(KeyCode::Char('m'), KeyModifiers::ALT);
+    backend.push_key(KeyCode::Char('l'), KeyModifiers::ALT);
+    backend.push_str("10\n");
+    backend.push_str("xy");
+    key(&backend, KeyCode::Esc);
+    ctrl(&backend, 's');
+    ctrl(&backend, 'q');
+    run(&backend, Document::open(path.to_str().unwrap()).unwrap());
+
+    assert!(backend.frames().iter().any(|frame| {
+        shows(frame, "Played the macro 1 time(s), stopped as it was interrupted")
+    }));
+    assert_eq!(fs::read_to_string(&path).unwrap(), "a!\nb!\nc\nd\n");
+    fs::remove_file(path).ok();
+}